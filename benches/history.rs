@@ -0,0 +1,89 @@
+//! List and total over a long history. Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use metronome::filters::Filter;
+use metronome::store::sqlite::SqliteStore;
+use metronome::store::TaskStore;
+use metronome::{Config, ListMode, ListOptions, Task, TaskStatus, Tracker};
+
+const TASKS: i64 = 300_000;
+const NOW: i64 = 1713800000;
+
+// About 20 years of tasks, one every half hour
+fn history() -> Tracker {
+    let mut store = SqliteStore::open_in_memory().unwrap();
+    store.begin().unwrap();
+    for i in 0..TASKS {
+        let start_time = NOW - (TASKS - i) * 1800;
+        let active = i >= TASKS - 3;
+        store
+            .insert(Task {
+                id: 0,
+                name: format!("Task {}", i % 500),
+                start_time,
+                end_time: (!active).then_some(start_time + 1500),
+                total_time: (!active).then_some(1500),
+                category: format!("Category {}", i % 12),
+                status: if active {
+                    TaskStatus::Active
+                } else {
+                    TaskStatus::Complete
+                },
+                tags: vec![],
+                utc_offset: Some(0),
+                uuid: String::new(),
+                modified_at: start_time,
+                invoice: None,
+            })
+            .unwrap();
+    }
+    store.commit().unwrap();
+    Tracker::new(Box::new(store), Config::default())
+        .with_clock(metronome::clock::FixedClock::new(NOW))
+}
+
+fn bench_history(c: &mut Criterion) {
+    let tracker = history();
+
+    c.bench_function("list active", |b| {
+        b.iter(|| {
+            tracker
+                .list(&ListOptions::new(ListMode::Active, Filter::All))
+                .unwrap()
+        })
+    });
+    c.bench_function("list last day", |b| {
+        b.iter(|| {
+            tracker
+                .list(&ListOptions::new(ListMode::All, Filter::Day))
+                .unwrap()
+        })
+    });
+    c.bench_function("list newest page", |b| {
+        let options = ListOptions {
+            limit: Some(50),
+            offset: 50,
+            descending: true,
+            ..Default::default()
+        };
+        b.iter(|| tracker.list(&options).unwrap())
+    });
+    c.bench_function("total last week", |b| {
+        b.iter(|| tracker.totals(Filter::Week, None).unwrap())
+    });
+    c.bench_function("total category last month", |b| {
+        b.iter(|| tracker.totals(Filter::Month, Some("Category 3")).unwrap())
+    });
+    c.bench_function("total all", |b| {
+        b.iter(|| tracker.totals(Filter::All, None).unwrap())
+    });
+    c.bench_function("prompt", |b| b.iter(|| tracker.prompt_task().unwrap()));
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_history
+}
+criterion_main!(benches);
@@ -1,10 +1,69 @@
-use clap::builder::NonEmptyStringValueParser;
+use chrono::{DateTime, NaiveDate};
+use clap::builder::{NonEmptyStringValueParser, PossibleValuesParser, TypedValueParser};
 use clap::{command, Arg, ArgAction, ArgMatches, Command};
+use std::str::FromStr;
+
+use crate::output::Column;
+
+use metronome::config::{self, OutputFormat};
+use metronome::invoice::InvoiceFormat;
+use metronome::store::SortKey;
+use metronome::tasktime::TaskTime;
+use metronome::timezone::DisplayZone;
+use metronome::DB_NAME;
 
 pub fn match_cli() -> ArgMatches {
-    let matches = command!()
-        .args_conflicts_with_subcommands(true)
+    command!()
         .arg_required_else_help(true)
+        .subcommand_required(true)
+        .arg(
+            Arg::new("tz")
+                .help(
+                    "Time zone to display times in: local, recorded (the zone each task was \
+                    started in, while days and weeks stay local), an IANA name such as \
+                    Europe/Berlin, or an offset such as +02:00.",
+                )
+                .long("tz")
+                .global(true)
+                .value_parser(DisplayZone::from_str),
+        )
+        .arg(
+            Arg::new("db")
+                .help(
+                    "Store to record tasks in. Files ending in .jsonl are kept as JSON lines, \
+                    anything else as a SQLite database.",
+                )
+                .long("db")
+                .global(true)
+                .default_value(DB_NAME)
+                .value_parser(NonEmptyStringValueParser::new()),
+        )
+        .arg(
+            Arg::new("output")
+                .help("Print lists, totals and reports as a table, CSV or JSON.")
+                .long("output")
+                .global(true)
+                .value_parser(
+                    PossibleValuesParser::new(["table", "csv", "json"])
+                        .try_map(|format| OutputFormat::from_str(&format)),
+                ),
+        )
+        .arg(
+            Arg::new("force")
+                .help("Change locked tasks anyway. Every such change is logged.")
+                .long("force")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            // Pins the current time, e.g. to reproduce a user report
+            Arg::new("now")
+                .help("Use this time instead of the system clock (RFC 3339 or unix timestamp).")
+                .long("now")
+                .global(true)
+                .hide(true)
+                .value_parser(parse_now),
+        )
         .subcommand(
             Command::new("start")
                 .about("Start a new task.")
@@ -20,6 +79,14 @@ pub fn match_cli() -> ArgMatches {
                         .short('c')
                         .long("category")
                         .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .help("Add a tag to the new task. Can be given multiple times.")
+                        .short('t')
+                        .long("tag")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_tag),
                 ),
         )
         .subcommand(
@@ -27,10 +94,10 @@ pub fn match_cli() -> ArgMatches {
                 .about("End an existing task.")
                 .arg(
                     Arg::new("task")
-                        .help("Name of the task to end.")
+                        .help("Name of the task to end. Every active task of that name is ended.")
                         .value_parser(NonEmptyStringValueParser::new())
                         .required_unless_present_any(["all", "last"])
-                        .exclusive(true),
+                        .conflicts_with_all(["last", "all"]),
                 )
                 .arg(
                     Arg::new("last")
@@ -38,14 +105,51 @@ pub fn match_cli() -> ArgMatches {
                         .long("last")
                         .help("Ends the active task that was started most recently.")
                         .action(ArgAction::SetTrue)
-                        .exclusive(true),
+                        .conflicts_with("all"),
                 )
                 .arg(
                     Arg::new("all")
                         .long("all")
-                        .help("Ends all active tasks. Overrides a task name if one is given.")
+                        .help("Ends every active task, whatever its name.")
                         .action(ArgAction::SetTrue)
-                        .exclusive(true),
+                        .conflicts_with("last"),
+                ),
+        )
+        .subcommand(
+            Command::new("switch")
+                .about("End the active tasks and start a new task at the same moment.")
+                .arg(
+                    Arg::new("task")
+                        .help("Name of the task to start")
+                        .value_parser(NonEmptyStringValueParser::new())
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("category")
+                        .help("Specify a category for the new task.")
+                        .short('c')
+                        .long("category")
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .help("Add a tag to the new task. Can be given multiple times.")
+                        .short('t')
+                        .long("tag")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_tag),
+                ),
+        )
+        .subcommand(
+            Command::new("continue")
+                .about("Start a new task with the same name, category and tags as a previous task.")
+                .arg(
+                    Arg::new("task")
+                        .help(
+                            "ID or name of the task to continue. \
+                            Defaults to the most recently ended task.",
+                        )
+                        .value_parser(NonEmptyStringValueParser::new()),
                 ),
         )
         .subcommand(
@@ -95,7 +199,86 @@ pub fn match_cli() -> ArgMatches {
                             "semiannual",
                             "y",
                             "year",
+                            "a",
+                            "all",
                         ]),
+                )
+                .arg(
+                    Arg::new("category")
+                        .help(
+                            "Only list tasks in this category, or not in it when prefixed \
+                            with ! (quote it in the shell, e.g. '!admin'). Can be given \
+                            multiple times.",
+                        )
+                        .long("category")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_category),
+                )
+                .arg(
+                    Arg::new("name")
+                        .help(
+                            "Only list tasks whose name matches a glob such as 'fix-*', \
+                            or a regular expression between slashes such as '/^fix-\\d+$/'.",
+                        )
+                        .long("name")
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new("min")
+                        .help("Only list tasks that took at least this long, e.g. 10m.")
+                        .long("min")
+                        .value_parser(TaskTime::from_str),
+                )
+                .arg(
+                    Arg::new("max")
+                        .help("Only list tasks that took at most this long, e.g. 4h.")
+                        .long("max")
+                        .value_parser(TaskTime::from_str),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .help("Show at most this many tasks.")
+                        .short('n')
+                        .long("limit")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("offset")
+                        .help("Skip this many tasks before the first one shown.")
+                        .long("offset")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("sort")
+                        .help(
+                            "Sort the tasks by start, end, duration, name or category, \
+                            optionally followed by :asc or :desc, e.g. duration:desc.",
+                        )
+                        .short('s')
+                        .long("sort")
+                        .default_value("start")
+                        .value_parser(parse_sort),
+                )
+                .arg(
+                    Arg::new("reverse")
+                        .help("Reverse the order, e.g. to show the newest tasks first.")
+                        .short('r')
+                        .long("reverse")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("columns")
+                        .help("Columns to show, in order, separated by commas.")
+                        .long("columns")
+                        .value_delimiter(',')
+                        .value_parser(
+                            PossibleValuesParser::new([
+                                "id", "name", "start", "end", "duration", "elapsed", "category",
+                                "tags", "status", "invoice",
+                            ])
+                            .try_map(|column| Column::from_str(&column)),
+                        ),
                 ),
         )
         .subcommand(
@@ -128,9 +311,263 @@ pub fn match_cli() -> ArgMatches {
                         .short('c')
                         .long("category")
                         .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new("money")
+                        .long("money")
+                        .help(
+                            "Add the billable time and the amount owed under the configured rates.",
+                        )
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("invoice")
+                .about("Bill a client's tasks, marking them so they are not billed again.")
+                .arg(
+                    Arg::new("client")
+                        .help("Category of the tasks to bill.")
+                        .short('c')
+                        .long("client")
+                        .required(true)
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new("since")
+                        .help("First day to bill, e.g. 2024-04-01.")
+                        .long("since")
+                        .required(true)
+                        .value_parser(parse_date),
+                )
+                .arg(
+                    Arg::new("until")
+                        .help("Last day to bill. Defaults to today.")
+                        .long("until")
+                        .value_parser(parse_date),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Write the invoice as Markdown, HTML or plain text.")
+                        .short('f')
+                        .long("format")
+                        .default_value("markdown")
+                        .value_parser(
+                            PossibleValuesParser::new(["markdown", "md", "html", "text", "txt"])
+                                .try_map(|format| InvoiceFormat::from_str(&format)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("lock")
+                .about(
+                    "Make the tasks started before a date read-only, e.g. once a timesheet \
+                    has been submitted. Shows the current lock without --until.",
+                )
+                .arg(
+                    Arg::new("until")
+                        .help("First day that is not locked, e.g. 2024-05-01.")
+                        .long("until")
+                        .value_parser(parse_date),
+                ),
+        )
+        .subcommand(
+            Command::new("gaps")
+                .about("List the untracked periods of the configured work hours on a day.")
+                .arg(
+                    Arg::new("day")
+                        .help("Day to check, e.g. 2024-05-01. Defaults to today.")
+                        .long("day")
+                        .value_parser(parse_date),
+                )
+                .arg(
+                    Arg::new("fill")
+                        .long("fill")
+                        .help("Ask for a task to record in each gap.")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("overlaps")
+                .about("List the tasks that ran at the same time as another task.")
+                .arg(
+                    Arg::new("day")
+                        .help("Only list the overlaps on this day, e.g. 2024-05-01.")
+                        .long("day")
+                        .value_parser(parse_date),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about(
+                    "Check the stored tasks for problems such as wrong total times, \
+                    overlapping tasks or tasks left running, and optionally fix them.",
+                )
+                .arg(
+                    Arg::new("fix")
+                        .long("fix")
+                        .help("Fix the problems that can be fixed without knowing what really happened.")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("stale-after")
+                        .help("Report tasks that have been active for longer than this, e.g. 3d.")
+                        .long("stale-after")
+                        .default_value("24h")
+                        .value_parser(TaskTime::from_str),
+                ),
+        )
+        .subcommand(
+            Command::new("log")
+                .about(
+                    "Show the history of a task: who changed it, when, with which command, \
+                    and its old and new values.",
+                )
+                .arg(
+                    Arg::new("id")
+                        .help("ID of the task.")
+                        .required(true)
+                        .value_parser(clap::value_parser!(i64)),
+                ),
+        )
+        .subcommand(Command::new("goals").about(
+            "Show the time tracked today, this week and per category against the configured \
+            targets and budgets.",
+        ))
+        .subcommand(
+            Command::new("status").about(
+                "Show the currently running tasks and the time tracked today and this week.",
+            ),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Merge the tasks of another store into this one and back.")
+                .arg(
+                    Arg::new("store")
+                        .help("Store to sync with, a SQLite database or a .jsonl file")
+                        .value_parser(NonEmptyStringValueParser::new())
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Show or change the settings in the config file.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("get")
+                        .about("Print the value of a setting. Unset category, tag and weekday settings exit with code 4.")
+                        .arg(
+                            Arg::new("key")
+                                .help("Name of the setting")
+                                .value_parser(parse_config_key)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Change a setting in the config file.")
+                        .arg(
+                            Arg::new("key")
+                                .help("Name of the setting")
+                                .value_parser(parse_config_key)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("value")
+                                .help("New value of the setting")
+                                .required(true),
+                        ),
+                )
+                .subcommand(Command::new("list").about("Print every setting and its value.")),
+        )
+        .subcommand(
+            Command::new("prompt")
+                .about("Print the current task for use in a shell prompt or status line.")
+                .arg(
+                    Arg::new("format")
+                        .help(
+                            "Format of the output. Placeholders: {name}, {category}, {count}, \
+                            {start}, {elapsed}, {elapsed:short}",
+                        )
+                        .short('f')
+                        .long("format")
+                        .default_value(metronome::prompt::DEFAULT_FORMAT),
+                )
+                .arg(
+                    Arg::new("idle")
+                        .help("Text to print when no task is active.")
+                        .long("idle")
+                        .default_value(""),
                 ),
         )
-        .get_matches();
+        .get_matches()
+}
+
+// Tags are stored comma separated, so a single tag cannot contain a comma
+fn parse_tag(tag: &str) -> Result<String, String> {
+    if tag.is_empty() {
+        Err(String::from("tags cannot be empty"))
+    } else if tag.contains(',') {
+        Err(String::from("tags cannot contain commas"))
+    } else {
+        Ok(tag.to_string())
+    }
+}
+
+// A setting of the config file, including those of categories such as category.Work.rounding
+fn parse_config_key(key: &str) -> Result<String, String> {
+    if config::is_key(key) {
+        Ok(key.to_string())
+    } else {
+        Err(format!(
+            "expected one of {}, category.<name>.<{}>, tag.<name>.<{}> or target.<weekday>",
+            config::KEYS.join(", "),
+            config::CATEGORY_KEYS.join("|"),
+            config::TAG_KEYS.join("|")
+        ))
+    }
+}
+
+// A category to include, or to exclude when prefixed with "!". Returns whether it is excluded.
+fn parse_category(category: &str) -> Result<(String, bool), String> {
+    let (name, excluded) = match category.strip_prefix('!') {
+        Some(name) => (name, true),
+        None => (category, false),
+    };
+    if name.is_empty() {
+        Err(String::from("categories cannot be empty"))
+    } else {
+        Ok((name.to_string(), excluded))
+    }
+}
+
+// A sort key with an optional direction, e.g. "duration:desc". Returns whether to sort descending.
+fn parse_sort(sort: &str) -> Result<(SortKey, bool), String> {
+    let (key, direction) = sort.split_once(':').unwrap_or((sort, "asc"));
+    let key = match SortKey::from_str(key) {
+        Ok(SortKey::Id) | Err(_) => {
+            return Err(String::from(
+                "expected start, end, duration, name or category",
+            ))
+        }
+        Ok(key) => key,
+    };
+    match direction {
+        "asc" => Ok((key, false)),
+        "desc" => Ok((key, true)),
+        _ => Err(String::from("the direction must be asc or desc")),
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| String::from("expected a date such as 2024-04-01"))
+}
 
-    matches
+fn parse_now(now: &str) -> Result<i64, String> {
+    if let Ok(timestamp) = now.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    DateTime::parse_from_rfc3339(now)
+        .map(|dt| dt.timestamp())
+        .map_err(|e| format!("expected an RFC 3339 time or unix timestamp: {}", e))
 }
@@ -1,244 +1,1101 @@
-pub(crate) mod filters;
-mod tasktime;
-
-use chrono::{DateTime, Local, TimeDelta};
-use fallible_streaming_iterator::FallibleStreamingIterator; // Needed to count returned SQLite Rows
-use rusqlite::{params, Connection, Rows, Statement};
-use std::iter::zip;
-
-use self::MetronomeResults::*;
-
+pub mod billing;
+pub mod clock;
+pub mod config;
+pub mod doctor;
+pub mod error;
+pub mod filters;
+pub mod gaps;
+pub mod invoice;
+pub mod prompt;
+pub mod store;
+pub mod sync;
+pub mod task;
+pub mod tasktime;
+pub mod timezone;
+
+use billing::{Billed, Rounding};
+use chrono::{Datelike, Days, NaiveDate, TimeDelta};
+use std::path::Path;
+use strum_macros::{Display, EnumString};
+
+use clock::{Clock, SystemClock};
+use config::Config;
+use doctor::Problem;
+use error::{MetronomeError, Result};
 use filters::Filter;
+use gaps::Gap;
+use invoice::Invoice;
+use prompt::PromptTask;
+use store::memory::MemoryStore;
+use store::{Change, ForcedChange, NamePattern, SortKey, TaskOrder, TaskQuery, TaskStore};
+use sync::SyncReport;
+use task::{Task, TaskStatus};
 use tasktime::TaskTime;
 
 pub const DB_NAME: &str = "tasks.db";
 
+/// A newly started task, along with any active tasks that were ended to make way for it.
 #[derive(Debug, PartialEq)]
-pub enum MetronomeResults {
-    CreateTable,
-    StartTask(i64),              // Returns start timestamp
-    EndTask(i64, i64, TaskTime), // Returns end timestamp, total time in seconds, TaskTime from total time
-    EndNoneActive,               // Returned when ending a task, but there are no tasks to end
-    EndAllActive(usize),         // Returns number of activities ended
-    List(usize),                 // Returns number of rows in the list
-    SumTaskTimes(i64),           // Returns total number of seconds for all tasks
+pub struct Started {
+    pub task: Task,
+    pub stopped: Vec<Task>,
 }
 
-pub fn create_task_table(connection: &Connection) -> rusqlite::Result<MetronomeResults> {
-    connection.execute(
-        "CREATE TABLE IF NOT EXISTS tasks (\
-        id INTEGER PRIMARY KEY NOT NULL, \
-        name TEXT NOT NULL, \
-        start_time INTEGER NOT NULL, \
-        end_time INTEGER, \
-        total_time INTEGER, \
-        category TEXT NOT NULL, \
-        status TEXT NOT NULL\
-        )",
-        (),
-    )?;
-    Ok(CreateTable)
+/// Time spent on the tasks of one category.
+#[derive(Debug, PartialEq)]
+pub struct CategoryTotal {
+    pub category: String,
+    pub time: TaskTime,
+    pub rounded: TaskTime, // Billed time under the category's rounding rule
+    pub billed: Option<Billed>, // Only worked out by `Tracker::billed_totals`
 }
 
-// START FUNCTIONS
-pub fn start_task(
-    connection: &Connection,
-    task_name: &String,
-    category: Option<&String>,
-) -> rusqlite::Result<MetronomeResults> {
-    // Task start time
-    let start_time_dt = Local::now();
-
-    // Values for creating new task
-    let default_category = "Misc".to_string();
-    let category = category.unwrap_or_else(|| &default_category);
-    let start_time = start_time_dt.timestamp();
-    let status = "Active";
-
-    connection.execute(
-        "INSERT INTO tasks (name, start_time, category, status) VALUES\
-        (?1, ?2, ?3, ?4)",
-        params![task_name, start_time, category, status],
-    )?;
-
-    println!(
-        "Task \"{}\" started at {}!",
-        task_name,
-        start_time_dt.format("%c")
-    );
-
-    Ok(StartTask(start_time))
+/// Time spent per category, largest first.
+#[derive(Debug, PartialEq)]
+pub struct Totals {
+    pub categories: Vec<CategoryTotal>,
+    pub time: TaskTime, // Sum over all categories
+    pub rounded: TaskTime,
+    pub billed: Option<Billed>,
 }
 
-// END FUNCTIONS
-pub fn end_task(connection: &Connection, task_name: &String) -> rusqlite::Result<MetronomeResults> {
-    let end_time_dt = Local::now();
+impl Totals {
+    /// Share of the overall billed time spent on `category`, from 0 to 100.
+    pub fn percentage(&self, category: &CategoryTotal) -> f32 {
+        if self.rounded == TaskTime::ZERO {
+            return 0f32;
+        }
+        (category.rounded.total_seconds() as f32 / self.rounded.total_seconds() as f32) * 100f32
+    }
+}
 
-    let mut stmt = connection.prepare("SELECT start_time FROM tasks WHERE name = ?")?;
-    let start_time_result: Result<i64, _> = stmt.query_row(params![task_name], |row| row.get(0));
+/// The running tasks and the time tracked so far today.
+#[derive(Debug, PartialEq)]
+pub struct Status {
+    pub active: Vec<Task>,
+    pub today: TaskTime,
+    pub week: TaskTime, // Since the start of the week set in the config
+    pub now: i64,       // Timestamp the elapsed times were measured at
+}
 
-    match start_time_result {
-        Ok(start_time) => {
-            println!(
-                "Ending task \"{}\" at {}",
-                task_name,
-                end_time_dt.format("%c")
-            );
+/// Time tracked toward a target, counting active tasks up to now.
+#[derive(Debug, PartialEq)]
+pub struct Goal {
+    pub name: String, // "today", "this week" or a category
+    pub tracked: TaskTime,
+    pub target: TaskTime,
+    pub expected: TaskTime, // The share of the target due by the end of today
+}
+
+impl Goal {
+    /// Share of the target tracked so far, from 0 and over 100 once the target is exceeded.
+    pub fn percentage(&self) -> f32 {
+        if self.target == TaskTime::ZERO {
+            return 100f32;
+        }
+        (self.tracked.total_seconds() as f32 / self.target.total_seconds() as f32) * 100f32
+    }
+}
 
-            let end_time = end_time_dt.timestamp();
-            let total_time = end_time - start_time;
-            let status = "Complete";
+/// Progress toward the daily and weekly targets and the weekly budgets of categories.
+#[derive(Debug, PartialEq)]
+pub struct Goals {
+    pub today: Option<Goal>, // None without a target for today
+    pub week: Option<Goal>,  // None without any daily or weekday targets
+    pub budgets: Vec<Goal>,  // One per category with a weekly budget
+    pub now: i64,            // Timestamp the tracked times were measured at
+}
 
-            connection.execute(
-                "UPDATE tasks SET end_time = ?1, total_time = ?2, status = ?3 WHERE name = ?4",
-                params![end_time, total_time, status, task_name],
-            )?;
+/// Which tasks are included in a list.
+#[derive(Debug, Default, Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum ListMode {
+    #[default]
+    All,
+    Active,
+    #[strum(serialize = "complete", serialize = "completed")]
+    Complete,
+}
 
-            let task_time = TaskTime::from(total_time);
+/// Which tasks a list includes, in which order, and which page of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListOptions {
+    pub mode: ListMode,
+    pub filter: Filter,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub limit: Option<usize>,
+    pub offset: usize,
+    pub categories: Vec<String>, // Any of these, every category when empty
+    pub excluded_categories: Vec<String>,
+    pub name: Option<NamePattern>,
+    pub min_duration: Option<i64>, // Seconds, active tasks count up to now
+    pub max_duration: Option<i64>,
+}
 
-            println!("Task \"{}\" ended after {}", task_name, task_time);
+impl ListOptions {
+    pub fn new(mode: ListMode, filter: Filter) -> Self {
+        Self {
+            mode,
+            filter,
+            ..Default::default()
+        }
+    }
+}
 
-            Ok(EndTask(end_time, total_time, task_time))
+impl Default for ListOptions {
+    // Oldest first
+    fn default() -> Self {
+        Self {
+            mode: ListMode::default(),
+            filter: Filter::default(),
+            sort: SortKey::Start,
+            descending: false,
+            limit: None,
+            offset: 0,
+            categories: vec![],
+            excluded_categories: vec![],
+            name: None,
+            min_duration: None,
+            max_duration: None,
         }
-        Err(e) => {
-            if e == rusqlite::Error::QueryReturnedNoRows {
-                println!("{} is not an active task!", task_name);
-                Ok(EndNoneActive)
-            } else {
+    }
+}
+
+/// Records tasks in a `TaskStore`.
+pub struct Tracker {
+    store: Box<dyn TaskStore>,
+    config: Config,
+    clock: Box<dyn Clock>,
+    in_transaction: bool,
+    force: bool,  // Change locked tasks anyway
+    user: String, // Recorded in the history of each task
+}
+
+impl Tracker {
+    /// Opens the store at `path`, creating it if needed. See `store::open` for the backends.
+    pub fn open<P: AsRef<Path>>(path: P, config: Config) -> Result<Self> {
+        Ok(Self::new(store::open(path)?, config))
+    }
+
+    /// Opens an existing store without creating or migrating anything.
+    pub fn open_read_only<P: AsRef<Path>>(path: P, config: Config) -> Result<Self> {
+        Ok(Self::new(store::open_read_only(path)?, config))
+    }
+
+    /// A Tracker that keeps its tasks in memory only.
+    pub fn open_in_memory(config: Config) -> Self {
+        Self::new(Box::new(MemoryStore::new()), config)
+    }
+
+    pub fn new(store: Box<dyn TaskStore>, config: Config) -> Self {
+        Self {
+            store,
+            config,
+            clock: Box::new(SystemClock),
+            in_transaction: false,
+            force: false,
+            user: current_user(),
+        }
+    }
+
+    /// Replaces the system clock, e.g. with a `FixedClock` for tests or reproducing reports.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Lets commands change locked tasks. Every such change is logged in the store.
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Replaces the user recorded in the history of each task, by default the one running
+    /// metronome.
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = String::from(user);
+        self
+    }
+
+    /// Current unix timestamp according to the Tracker's clock.
+    pub fn now(&self) -> i64 {
+        self.clock.now()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn close(self) -> Result<()> {
+        self.store.close()
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Task>> {
+        self.store.get(id)
+    }
+
+    /// Runs `change` in a store transaction, so either all of its changes are kept or none.
+    /// Every mutation goes through here, with the reads it depends on inside `change`, so
+    /// concurrent processes cannot interleave. Nested calls join the outer transaction.
+    fn atomically<T>(&mut self, change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.in_transaction {
+            return change(self);
+        }
+
+        self.store.begin()?;
+        self.in_transaction = true;
+        let result = change(self);
+        self.in_transaction = false;
+        match result {
+            Ok(value) => {
+                self.store.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                // The original error is more useful than a failed rollback
+                let _ = self.store.rollback();
                 Err(e)
             }
         }
     }
-}
 
-pub fn end_last(connection: &Connection) -> rusqlite::Result<MetronomeResults> {
-    let last_task_result = connection.query_row(
-        "SELECT name FROM tasks WHERE start_time = (SELECT MAX(start_time) FROM tasks WHERE status = 'Active')",
-        (),
-        |row| row.get(0),
-    );
-    match last_task_result {
-        Ok(last_task) => end_task(&connection, &last_task),
-        Err(e) => {
-            if e == rusqlite::Error::QueryReturnedNoRows {
-                println!("No active tasks to end!");
-                Ok(EndNoneActive)
-            } else {
+    // START FUNCTIONS
+    pub fn start(
+        &mut self,
+        task_name: &str,
+        category: Option<&str>,
+        tags: &[String],
+    ) -> Result<Started> {
+        self.start_task(task_name, category, tags, "start")
+    }
+
+    // END FUNCTIONS
+    /// Ends every active task named `task_name`, returning the tasks that were ended, oldest
+    /// first.
+    pub fn end(&mut self, task_name: &str) -> Result<Vec<Task>> {
+        let end_time = self.clock.now();
+
+        self.atomically(|tracker| {
+            // Completed tasks with the same name are left alone, so a task is never ended twice
+            let tasks = tracker.store.query(&TaskQuery {
+                status: Some(TaskStatus::Active),
+                name: Some(String::from(task_name)),
+                order: TaskOrder::asc(SortKey::Start),
+                ..Default::default()
+            })?;
+            if tasks.is_empty() {
+                return Err(MetronomeError::NotFound(format!(
+                    "\"{}\" is not an active task",
+                    task_name
+                )));
+            }
+
+            tasks
+                .into_iter()
+                .map(|task| tracker.complete(task, end_time, "end"))
+                .collect()
+        })
+    }
+
+    /// Ends the active task that was started most recently, and no other task of its name.
+    pub fn end_last(&mut self) -> Result<Task> {
+        let end_time = self.clock.now();
+
+        self.atomically(|tracker| match tracker.store.latest_active()? {
+            Some((last_task, _)) => tracker.complete(last_task, end_time, "end"),
+            None => Err(MetronomeError::NotFound(String::from(
+                "there are no active tasks to end",
+            ))),
+        })
+    }
+
+    /// Ends every active task, returning the tasks that were ended.
+    pub fn end_all(&mut self) -> Result<Vec<Task>> {
+        let end_time = self.clock.now();
+        self.atomically(|tracker| tracker.complete_active_tasks(end_time, "end"))
+    }
+
+    // CONTINUE FUNCTIONS
+    /// Starts a task with the same name, category and tags as `task`, which is an ID or a name.
+    /// Without a task the most recently ended task is continued.
+    pub fn continue_task(&mut self, task: Option<&str>) -> Result<Started> {
+        self.atomically(|tracker| {
+            // An ID takes precedence over a task name that happens to be a number
+            let previous = match task {
+                Some(task) => match task.parse::<i64>() {
+                    Ok(id) => tracker.get(id)?,
+                    Err(_) => tracker.first(TaskQuery {
+                        name: Some(String::from(task)),
+                        order: TaskOrder::desc(SortKey::Start),
+                        ..Default::default()
+                    })?,
+                },
+                None => tracker.first(TaskQuery {
+                    status: Some(TaskStatus::Complete),
+                    order: TaskOrder::desc(SortKey::End),
+                    ..Default::default()
+                })?,
+            };
+
+            match previous {
+                Some(previous) if previous.status == TaskStatus::Active => Err(
+                    MetronomeError::Conflict(format!("task \"{}\" is still active", previous.name)),
+                ),
+                Some(previous) => tracker.start_task(
+                    &previous.name,
+                    Some(&previous.category),
+                    &previous.tags,
+                    "continue",
+                ),
+                None => Err(MetronomeError::NotFound(match task {
+                    Some(task) => format!("no previous task matching \"{}\"", task),
+                    None => String::from("there are no completed tasks to continue"),
+                })),
+            }
+        })
+    }
+
+    // SWITCH FUNCTIONS
+    /// Ends the active tasks and starts a new one at the same moment.
+    pub fn switch(
+        &mut self,
+        task_name: &str,
+        category: Option<&str>,
+        tags: &[String],
+    ) -> Result<Started> {
+        // The same timestamp ends the old tasks and starts the new one so no gap is left
+        let switch_time = self.clock.now();
+
+        self.atomically(|tracker| {
+            let stopped = tracker.complete_active_tasks(switch_time, "switch")?;
+            let task =
+                tracker.insert_active_task(task_name, category, tags, switch_time, "switch")?;
+            Ok(Started { task, stopped })
+        })
+    }
+
+    // LIST FUNCTIONS
+    pub fn list(&self, options: &ListOptions) -> Result<Vec<Task>> {
+        let status = match options.mode {
+            ListMode::All => None,
+            ListMode::Active => Some(TaskStatus::Active),
+            ListMode::Complete => Some(TaskStatus::Complete),
+        };
+        let now = self.clock.now();
+        self.store.query(&TaskQuery {
+            status,
+            name_pattern: options.name.clone(),
+            categories: options.categories.clone(),
+            excluded_categories: options.excluded_categories.clone(),
+            started_after: parse_filter(options.filter, now),
+            min_duration: options.min_duration,
+            max_duration: options.max_duration,
+            now: Some(now),
+            order: TaskOrder {
+                key: options.sort,
+                descending: options.descending,
+            },
+            limit: options.limit,
+            offset: options.offset,
+            ..Default::default()
+        })
+    }
+
+    // TOTAL FUNCTIONS
+    pub fn totals(&self, filter: Filter, category: Option<&str>) -> Result<Totals> {
+        self.sum_totals(filter, category, false)
+    }
+
+    /// Like `totals`, with the billable time and amounts of each category.
+    pub fn billed_totals(&self, filter: Filter, category: Option<&str>) -> Result<Totals> {
+        self.sum_totals(filter, category, true)
+    }
+
+    fn sum_totals(&self, filter: Filter, category: Option<&str>, bill: bool) -> Result<Totals> {
+        let query = TaskQuery {
+            categories: category.map(String::from).into_iter().collect(),
+            started_after: parse_filter(filter, self.clock.now()),
+            ..Default::default()
+        };
+        let mut categories = self.store.totals(&query)?;
+
+        // Rounding and billing need the individual tasks, so they are only read when needed
+        let mut billed = bill.then(Billed::default);
+        for total in &mut categories {
+            let rounding = self.config.rounding_for(&total.category);
+            if rounding == Rounding::NONE && !bill {
+                continue;
+            }
+            let tasks = self.store.query(&TaskQuery {
+                status: Some(TaskStatus::Complete),
+                categories: vec![total.category.clone()],
+                ..query.clone()
+            })?;
+            total.rounded = rounding.apply(&tasks, &self.config.timezone);
+
+            if let Some(billed) = &mut billed {
+                let tasks = tasks
+                    .into_iter()
+                    .map(|task| {
+                        let billing = self.config.billing_for(&task);
+                        (task, billing)
+                    })
+                    .collect();
+                let category_billed = Billed::bill(tasks, &rounding, &self.config.timezone);
+                *billed += &category_billed;
+                total.billed = Some(category_billed);
+            }
+        }
+
+        Ok(Totals {
+            time: categories.iter().map(|category| category.time).sum(),
+            rounded: categories.iter().map(|category| category.rounded).sum(),
+            billed,
+            categories,
+        })
+    }
+
+    // STATUS FUNCTIONS
+    pub fn status(&self) -> Result<Status> {
+        let now = self.clock.now();
+
+        let active = self.store.query(&TaskQuery {
+            status: Some(TaskStatus::Active),
+            order: TaskOrder::asc(SortKey::Start),
+            ..Default::default()
+        })?;
+
+        let timezone = &self.config.timezone;
+        let week_start = timezone.start_of_week(now, self.config.week_start);
+
+        Ok(Status {
+            active,
+            today: self.tracked_between(timezone.start_of_day(now), now, None)?,
+            week: self.tracked_between(week_start, now, None)?,
+            now,
+        })
+    }
+
+    // GOAL FUNCTIONS
+    /// Time tracked today and this week against the configured targets, and this week in each
+    /// category with a weekly budget. A week's target is the sum of its days' targets.
+    pub fn goals(&self) -> Result<Goals> {
+        let now = self.clock.now();
+        let zone = &self.config.timezone;
+        let week_start = zone.start_of_week(now, self.config.week_start);
+        let today = zone.date(now).unwrap_or_default();
+        let first_day = zone.date(week_start).unwrap_or(today);
+
+        // The targets of every day of the week, and of the days up to and including today
+        let days: Vec<(NaiveDate, TaskTime)> = first_day
+            .iter_days()
+            .take(7)
+            .map(|date| {
+                let target = self.config.target_for(date.weekday());
+                (date, target.unwrap_or(TaskTime::ZERO))
+            })
+            .collect();
+        let week_target: TaskTime = days.iter().map(|(_, target)| *target).sum();
+        let due: TaskTime = days
+            .iter()
+            .filter(|(date, _)| *date <= today)
+            .map(|(_, target)| *target)
+            .sum();
+        let days_done = days.iter().filter(|(date, _)| *date <= today).count();
+
+        let today = match self.config.target_for(today.weekday()) {
+            Some(target) => Some(Goal {
+                name: String::from("today"),
+                tracked: self.tracked_between(zone.start_of_day(now), now, None)?,
+                target,
+                expected: target,
+            }),
+            None => None,
+        };
+        let has_targets = self.config.daily_target.is_some()
+            || self.config.weekday_targets.iter().any(Option::is_some);
+        let week = if has_targets {
+            Some(Goal {
+                name: String::from("this week"),
+                tracked: self.tracked_between(week_start, now, None)?,
+                target: week_target,
+                expected: due,
+            })
+        } else {
+            None
+        };
+
+        let mut budgets = vec![];
+        for (category, settings) in &self.config.categories {
+            let Some(budget) = settings.weekly_budget else {
+                continue;
+            };
+            // Budgets are spread over the week like the targets, or evenly without any
+            let share = match week_target.total_seconds() {
+                0 => days_done as f64 / 7.0,
+                week => due.total_seconds() as f64 / week as f64,
+            };
+            budgets.push(Goal {
+                name: category.clone(),
+                tracked: self.tracked_between(week_start, now, Some(category))?,
+                target: budget,
+                expected: TaskTime::from((budget.total_seconds() as f64 * share).round() as i64),
+            });
+        }
+
+        Ok(Goals {
+            today,
+            week,
+            budgets,
+            now,
+        })
+    }
+
+    // INVOICE FUNCTIONS
+    /// Bills the completed tasks of `client`, a category, started from `since` to `until`
+    /// inclusive and not yet on an invoice. Non-billable tasks are left out. The tasks are
+    /// marked with the new invoice's number, so they are never billed twice.
+    pub fn invoice(&mut self, client: &str, since: NaiveDate, until: NaiveDate) -> Result<Invoice> {
+        if until < since {
+            return Err(MetronomeError::Validation(format!(
+                "the invoice period ends on {} before it starts on {}",
+                until, since
+            )));
+        }
+
+        self.atomically(|tracker| {
+            let zone = tracker.config.timezone;
+            let tasks: Vec<Task> = tracker
+                .store
+                .query(&TaskQuery {
+                    status: Some(TaskStatus::Complete),
+                    categories: vec![String::from(client)],
+                    started_after: Some(zone.midnight(since) - 1),
+                    started_before: Some(zone.midnight(until + Days::new(1))),
+                    order: TaskOrder::asc(SortKey::Start),
+                    ..Default::default()
+                })?
+                .into_iter()
+                .filter(|task| task.invoice.is_none() && tracker.config.billing_for(task).billable)
+                .collect();
+            if tasks.is_empty() {
+                return Err(MetronomeError::NotFound(format!(
+                    "there are no tasks in {} from {} to {} left to invoice",
+                    client, since, until
+                )));
+            }
+
+            let (days, currency) = invoice::itemize(client, &tasks, &tracker.config)?;
+            let now = tracker.clock.now();
+            let number = tracker.store.next_invoice_number(client, now)?;
+            for task in tasks {
+                let invoiced = Task {
+                    invoice: Some(number),
+                    modified_at: now,
+                    ..task.clone()
+                };
+                tracker.update(task, &invoiced, "invoice")?;
+            }
+
+            Ok(Invoice {
+                number,
+                client: String::from(client),
+                date: zone.date(now).unwrap_or(until),
+                since,
+                until,
+                currency,
+                days,
+                tax_rate: tracker.config.tax_for(client),
+            })
+        })
+    }
+
+    // PROMPT FUNCTIONS
+    /// The most recently started active task. Cheap enough to run on every shell prompt.
+    pub fn prompt_task(&self) -> Result<Option<PromptTask>> {
+        let task = self
+            .store
+            .latest_active()?
+            .map(|(task, active_count)| PromptTask {
+                name: task.name,
+                category: task.category,
+                start_time: task.start_time,
+                active_count: active_count as i64,
+            });
+        Ok(task)
+    }
+
+    // SYNC FUNCTIONS
+    /// Merges the tasks of this Tracker's store and `other`, so both end up with every task.
+    /// Nothing is changed in either store if the merge fails, or if it would add or change
+    /// locked tasks in this store without `with_force`.
+    pub fn sync(&mut self, other: &mut dyn TaskStore) -> Result<SyncReport> {
+        other.begin()?;
+        let merged = self.atomically(|tracker| {
+            let report = sync::merge(tracker.store.as_mut(), other)?;
+            // Copying tasks into a locked period changes it as much as editing them does.
+            // A changed task is locked if either version of it started before the lock.
+            let changed = report
+                .conflicts
+                .iter()
+                .filter(|conflict| conflict.kept_remote())
+                .map(|conflict| Task {
+                    id: conflict.local.id,
+                    start_time: conflict.local.start_time.min(conflict.remote.start_time),
+                    ..conflict.remote.clone()
+                });
+            for task in report.pulled.iter().cloned().chain(changed) {
+                tracker.check_lock(&task, "sync")?;
+            }
+
+            for task in &report.pulled {
+                tracker.log_change(None, task, "sync")?;
+            }
+            for conflict in report.conflicts.iter().filter(|c| c.kept_remote()) {
+                let new = Task {
+                    id: conflict.local.id,
+                    ..conflict.remote.clone()
+                };
+                tracker.log_change(Some(conflict.local.clone()), &new, "sync")?;
+            }
+            // The other store keeps a history of what the sync changed in it too
+            for task in &report.pushed {
+                other.log_change(&tracker.change(None, task, "sync"))?;
+            }
+            for conflict in report.conflicts.iter().filter(|c| !c.kept_remote()) {
+                let new = Task {
+                    id: conflict.remote.id,
+                    ..conflict.local.clone()
+                };
+                other.log_change(&tracker.change(Some(conflict.remote.clone()), &new, "sync"))?;
+            }
+            Ok(report)
+        });
+        match merged {
+            Ok(report) => {
+                other.commit()?;
+                Ok(report)
+            }
+            Err(e) => {
+                let _ = other.rollback();
                 Err(e)
             }
         }
     }
-}
 
-pub fn end_all_active(connection: &Connection) -> rusqlite::Result<MetronomeResults> {
-    let end_time_dt = Local::now();
-    let end_time = end_time_dt.timestamp();
-    let status = "Complete";
-
-    //Get the number of active tasks
-    let mut stmt = connection.prepare("SELECT * FROM tasks WHERE status = 'Active'")?;
-    let num_ended_tasks = stmt.query(())?.count()?;
-    stmt.finalize()?;
-
-    // Only update tasks as needed
-    if num_ended_tasks > 0 {
-        // Update the end time for use in calculating total time
-        connection.execute(
-            "UPDATE tasks SET end_time = ?1 WHERE status = 'Active'",
-            params![end_time],
-        )?;
+    // LOCK FUNCTIONS
+    /// Makes the tasks started before `until` read-only, e.g. once a timesheet for them has
+    /// been submitted. Moving the lock back needs `with_force`.
+    pub fn lock(&mut self, until: NaiveDate) -> Result<()> {
+        let until = self.config.timezone.midnight(until);
+        self.atomically(|tracker| {
+            match tracker.store.locked_until()? {
+                Some(locked) if until < locked && !tracker.force => {
+                    return Err(MetronomeError::Conflict(format!(
+                        "tasks are locked until {}, moving the lock back needs --force",
+                        tracker.format_date(locked)
+                    )))
+                }
+                Some(locked) if until < locked => tracker.log_forced_change(None, "lock")?,
+                _ => {}
+            }
+            tracker.store.lock(until)
+        })
+    }
 
-        // Update total time and set status to complete
-        connection.execute(
-            "UPDATE tasks SET total_time = (end_time - start_time), status = ?1 WHERE status = 'Active'",
-            params![status])?;
+    /// Tasks started before this timestamp are locked.
+    pub fn locked_until(&self) -> Result<Option<i64>> {
+        self.store.locked_until()
+    }
 
-        println!(
-            "Ended {} active tasks at {}.",
-            num_ended_tasks,
-            end_time_dt.format("%c")
-        );
-        Ok(EndAllActive(num_ended_tasks))
-    } else {
-        println!("No active tasks to end.");
-        Ok(EndNoneActive)
+    /// Changes made to locked tasks with `with_force`, oldest first.
+    pub fn forced_changes(&self) -> Result<Vec<ForcedChange>> {
+        self.store.forced_changes()
     }
-}
 
-// LIST FUNCTIONS
+    // HISTORY FUNCTIONS
+    /// Every insert and update of the task with ID `id`, oldest first. Tasks recorded before
+    /// the history was kept may have none.
+    pub fn history(&self, id: i64) -> Result<Vec<Change>> {
+        let history = self.store.history(id)?;
+        if history.is_empty() && self.store.get(id)?.is_none() {
+            return Err(MetronomeError::NotFound(format!(
+                "there is no task with ID {}",
+                id
+            )));
+        }
+        Ok(history)
+    }
 
-fn list_from_stmt(
-    mut stmt: Statement,
-    filter_start_time: i64,
-) -> rusqlite::Result<MetronomeResults> {
-    let rows = stmt.query(params![filter_start_time])?;
-    let num_returned = print_list_rows(rows)?;
+    // DOCTOR FUNCTIONS
+    /// Finds the problems with the stored tasks. Tasks active for longer than `stale_after`
+    /// were most likely never ended.
+    pub fn check(&self, stale_after: TaskTime) -> Result<Vec<Problem>> {
+        let mut problems: Vec<Problem> = self
+            .store
+            .unknown_statuses()?
+            .into_iter()
+            .map(Problem::UnknownStatus)
+            .collect();
+        // A task with an unknown status cannot be read, so the others are read by status
+        let mut tasks = vec![];
+        for status in [TaskStatus::Active, TaskStatus::Complete] {
+            tasks.extend(self.store.query(&TaskQuery {
+                status: Some(status),
+                ..Default::default()
+            })?);
+        }
+        problems.extend(doctor::diagnose(&tasks, self.clock.now(), stale_after));
+        Ok(problems)
+    }
 
-    Ok(List(num_returned))
-}
-pub fn list_active(connection: &Connection, filter: Filter) -> rusqlite::Result<MetronomeResults> {
-    let start_time = parse_filter(filter);
-    let stmt = connection.prepare(
-        "SELECT * from tasks WHERE status = 'Active' \
-         AND start_time > ?1",
-    )?;
-    return list_from_stmt(stmt, start_time);
-}
+    /// Fixes every problem `check` finds that can be fixed, returning the problems fixed.
+    /// Unknown statuses become Complete for tasks with an end time and Active otherwise.
+    /// Problems that would change locked tasks are left for `check` to report, unless forced.
+    pub fn repair(&mut self, stale_after: TaskTime) -> Result<Vec<Problem>> {
+        let now = self.clock.now();
+        let zone = self.config.timezone;
+
+        self.atomically(|tracker| {
+            let locked_until = match tracker.force {
+                true => None,
+                false => tracker.store.locked_until()?,
+            };
+            let locked = |start_time: i64| locked_until.is_some_and(|locked| start_time < locked);
+
+            let mut fixed = vec![];
+            for unknown in tracker.store.unknown_statuses()? {
+                if locked(unknown.start_time) {
+                    continue;
+                }
+                let status = match unknown.end_time {
+                    Some(_) => TaskStatus::Complete,
+                    None => TaskStatus::Active,
+                };
+                tracker.store.set_status(unknown.id, status)?;
+                let Some(task) = tracker.store.get(unknown.id)? else {
+                    continue;
+                };
+                tracker.check_lock(&task, "doctor")?;
+                let repaired = Task {
+                    modified_at: now,
+                    ..task.clone()
+                };
+                tracker.store.update(&repaired)?;
+                let change = Change {
+                    old_status: Some(unknown.status.clone()),
+                    ..tracker.change(Some(task), &repaired, "doctor")
+                };
+                tracker.store.log_change(&change)?;
+                fixed.push(Problem::UnknownStatus(unknown));
+            }
 
-pub fn list_complete(
-    connection: &Connection,
-    filter: Filter,
-) -> rusqlite::Result<MetronomeResults> {
-    let start_time = parse_filter(filter);
-    let stmt = connection.prepare(
-        "SELECT * from tasks WHERE status = 'Complete' \
-        AND start_time > ?1",
-    )?;
-    return list_from_stmt(stmt, start_time);
-}
+            // A fix can fix or change other problems, e.g. ending a stale task can end its
+            // overlaps, so the problems are found again after each one
+            loop {
+                let next = tracker
+                    .check(stale_after)?
+                    .into_iter()
+                    .map(|problem| {
+                        let repaired = problem.repaired(now, &zone);
+                        (problem, repaired)
+                    })
+                    .find(|(_, repaired)| {
+                        !repaired.is_empty() && !repaired.iter().any(|task| locked(task.start_time))
+                    });
+                let Some((problem, repaired)) = next else {
+                    return Ok(fixed);
+                };
+                for task in repaired {
+                    let old = tracker.store.get(task.id)?.ok_or_else(|| {
+                        MetronomeError::NotFound(format!("there is no task with ID {}", task.id))
+                    })?;
+                    tracker.check_lock(&old, "doctor")?;
+                    tracker.update(old, &task, "doctor")?;
+                }
+                fixed.push(problem);
+            }
+        })
+    }
 
-pub fn list_all(connection: &Connection, filter: Filter) -> rusqlite::Result<MetronomeResults> {
-    let start_time = parse_filter(filter);
-    let stmt = connection.prepare("SELECT * from tasks WHERE start_time > ?1")?;
+    // GAP FUNCTIONS
+    /// The periods of the configured work hours on `date` in which no task was running, up to
+    /// now.
+    pub fn gaps(&self, date: NaiveDate) -> Result<Vec<Gap>> {
+        let (start, end) = self.work_hours(date);
+        let tasks = self.tasks_between(start, end)?;
+        Ok(gaps::find(&tasks, start, end, self.clock.now()))
+    }
 
-    return list_from_stmt(stmt, start_time);
-}
+    /// Records a completed task covering `gap`, which must still be untracked.
+    pub fn fill(
+        &mut self,
+        gap: Gap,
+        task_name: &str,
+        category: Option<&str>,
+        tags: &[String],
+    ) -> Result<Task> {
+        validate(task_name, tags)?;
+        self.atomically(|tracker| {
+            let tasks = tracker.tasks_between(gap.start, gap.end)?;
+            if gaps::find(&tasks, gap.start, gap.end, tracker.clock.now()) != vec![gap] {
+                return Err(MetronomeError::Conflict(String::from(
+                    "the gap is no longer untracked",
+                )));
+            }
+            let task = tracker.store.insert(Task {
+                id: 0, // Assigned by the store
+                name: String::from(task_name),
+                start_time: gap.start,
+                end_time: Some(gap.end),
+                total_time: Some(gap.end - gap.start),
+                category: String::from(category.unwrap_or(&tracker.config.default_category)),
+                status: TaskStatus::Complete,
+                tags: tags.to_vec(),
+                utc_offset: Some(timezone::local_offset(gap.start)),
+                uuid: String::new(), // Assigned by the store
+                modified_at: tracker.clock.now(),
+                invoice: None,
+            })?;
+            tracker.check_lock(&task, "fill")?;
+            tracker.log_change(None, &task, "fill")?;
+            Ok(task)
+        })
+    }
+
+    /// Every pair of tasks that ran at the same time, the one that started first first. With
+    /// a `day`, only the pairs that overlap on that day.
+    pub fn overlaps(&self, day: Option<NaiveDate>) -> Result<Vec<(Task, Task)>> {
+        let now = self.clock.now();
+        let Some(day) = day else {
+            return Ok(doctor::overlaps(
+                &self.store.query(&TaskQuery::default())?,
+                now,
+            ));
+        };
+
+        let zone = self.config.timezone;
+        let (start, end) = (zone.midnight(day), zone.midnight(day + Days::new(1)));
+        let tasks = self.tasks_between(start, end)?;
+        Ok(doctor::overlaps(&tasks, now)
+            .into_iter()
+            .filter(|(first, second)| {
+                let overlap_end = doctor::end_time(first, now).min(doctor::end_time(second, now));
+                second.start_time < end && overlap_end > start
+            })
+            .collect())
+    }
 
-// TOTAL FUNCTIONS
-pub fn sum_task_times(
-    connection: &Connection,
-    filter: Filter,
-    category: Option<&String>,
-) -> rusqlite::Result<MetronomeResults> {
-    let mut stmt: Statement;
-    let rows: Rows;
-    let start_time = parse_filter(filter);
-    match category {
-        Some(category) => {
-            stmt = connection.prepare(
-                "SELECT category, SUM(total_time) FROM tasks WHERE start_time > ?1 AND category = ?2 \
-                GROUP BY category ORDER BY SUM(total_time) DESC"
-            )?;
-            rows = stmt.query(params![start_time, category])?;
+    // HELPER FUNCTIONS
+
+    /// The time tasks ran between `start` and `end`, of the tasks in `category` if given. Only
+    /// the part of a task within the period counts, and active tasks run until now.
+    fn tracked_between(&self, start: i64, end: i64, category: Option<&str>) -> Result<TaskTime> {
+        let now = self.clock.now();
+        Ok(self
+            .tasks_between(start, end)?
+            .iter()
+            .filter(|task| category.is_none_or(|category| task.category == category))
+            .map(|task| {
+                let ran = doctor::end_time(task, now).min(end) - task.start_time.max(start);
+                TaskTime::from(ran.max(0))
+            })
+            .sum())
+    }
+
+    /// Start and end of the configured work hours on `date`.
+    fn work_hours(&self, date: NaiveDate) -> (i64, i64) {
+        let zone = self.config.timezone;
+        let hours = self.config.work_hours;
+        (zone.at(date, hours.start), zone.at(date, hours.end))
+    }
+
+    /// The tasks that ran at some point between `start` and `end`.
+    fn tasks_between(&self, start: i64, end: i64) -> Result<Vec<Task>> {
+        let now = self.clock.now();
+        // A task can start before the period and run into it
+        let tasks = self.store.query(&TaskQuery {
+            started_before: Some(end),
+            ..Default::default()
+        })?;
+        Ok(tasks
+            .into_iter()
+            .filter(|task| doctor::end_time(task, now) > start)
+            .collect())
+    }
+
+    /// Starts a task as `command`, ending the active tasks first if only one may be active.
+    fn start_task(
+        &mut self,
+        task_name: &str,
+        category: Option<&str>,
+        tags: &[String],
+        command: &str,
+    ) -> Result<Started> {
+        let start_time = self.clock.now();
+
+        self.atomically(|tracker| {
+            let stopped = if tracker.config.single_active {
+                tracker.complete_active_tasks(start_time, command)?
+            } else {
+                vec![]
+            };
+            let task =
+                tracker.insert_active_task(task_name, category, tags, start_time, command)?;
+            Ok(Started { task, stopped })
+        })
+    }
+
+    /// Replaces `old` with `new` in the store, keeping both in the task's history.
+    fn update(&mut self, old: Task, new: &Task, command: &str) -> Result<()> {
+        self.store.update(new)?;
+        self.log_change(Some(old), new, command)
+    }
+
+    fn log_change(&mut self, old: Option<Task>, new: &Task, command: &str) -> Result<()> {
+        let change = self.change(old, new, command);
+        self.store.log_change(&change)
+    }
+
+    fn change(&self, old: Option<Task>, new: &Task, command: &str) -> Change {
+        Change {
+            task_id: new.id,
+            command: String::from(command),
+            user: self.user.clone(),
+            changed_at: self.clock.now(),
+            old,
+            old_status: None,
+            new: new.clone(),
+        }
+    }
+
+    /// Fails if `task` is locked, unless forced, in which case the change is logged.
+    fn check_lock(&mut self, task: &Task, command: &str) -> Result<()> {
+        let Some(locked) = self.store.locked_until()? else {
+            return Ok(());
+        };
+        if task.start_time >= locked {
+            return Ok(());
         }
-        None => {
-            stmt = connection.prepare(
-                "SELECT category, SUM(total_time) FROM tasks WHERE start_time > ?1 \
-                GROUP BY category ORDER BY SUM(total_time) DESC",
-            )?;
-            rows = stmt.query(params![start_time])?;
+        if !self.force {
+            return Err(MetronomeError::Conflict(format!(
+                "task {} \"{}\" is locked, tasks started before {} can only be changed with \
+                --force",
+                task.id,
+                task.name,
+                self.format_date(locked)
+            )));
         }
+        self.log_forced_change(Some(task.id), command)
+    }
+
+    fn log_forced_change(&mut self, task_id: Option<i64>, command: &str) -> Result<()> {
+        self.store.log_forced_change(&ForcedChange {
+            task_id,
+            command: String::from(command),
+            changed_at: self.clock.now(),
+        })
+    }
+
+    fn format_date(&self, timestamp: i64) -> String {
+        self.config.timezone.format(timestamp, None, "%Y-%m-%d")
     }
 
-    // Print results
-    let sum_total_s = print_total_time_rows(rows)?;
+    fn first(&self, query: TaskQuery) -> Result<Option<Task>> {
+        let query = TaskQuery {
+            limit: Some(1),
+            ..query
+        };
+        Ok(self.store.query(&query)?.into_iter().next())
+    }
+
+    /// Stores a new active task, started by `command`.
+    fn insert_active_task(
+        &mut self,
+        task_name: &str,
+        category: Option<&str>,
+        tags: &[String],
+        start_time: i64,
+        command: &str,
+    ) -> Result<Task> {
+        validate(task_name, tags)?;
+
+        let task = self.store.insert(Task {
+            id: 0, // Assigned by the store
+            name: String::from(task_name),
+            start_time,
+            end_time: None,
+            total_time: None,
+            category: String::from(category.unwrap_or(&self.config.default_category)),
+            status: TaskStatus::Active,
+            tags: tags.to_vec(),
+            utc_offset: Some(timezone::local_offset(start_time)),
+            uuid: String::new(), // Assigned by the store
+            modified_at: start_time,
+            invoice: None,
+        })?;
+        self.check_lock(&task, command)?;
+        self.log_change(None, &task, command)?;
+        Ok(task)
+    }
+
+    /// Completes every active task at `end_time`, returning the tasks that were ended.
+    fn complete_active_tasks(&mut self, end_time: i64, command: &str) -> Result<Vec<Task>> {
+        let active = self.store.query(&TaskQuery {
+            status: Some(TaskStatus::Active),
+            order: TaskOrder::asc(SortKey::Start),
+            ..Default::default()
+        })?;
+
+        active
+            .into_iter()
+            .map(|task| self.complete(task, end_time, command))
+            .collect()
+    }
+
+    // Ends an active task at `end_time`, unless it is locked
+    fn complete(&mut self, task: Task, end_time: i64, command: &str) -> Result<Task> {
+        self.check_lock(&task, command)?;
+        let completed = Task {
+            end_time: Some(end_time),
+            total_time: Some(end_time - task.start_time),
+            status: TaskStatus::Complete,
+            modified_at: end_time,
+            ..task.clone()
+        };
+        self.update(task, &completed, command)?;
+        Ok(completed)
+    }
+}
 
-    Ok(SumTaskTimes(sum_total_s))
+// Tasks need a name, and tags cannot hold the commas they are stored apart with
+fn validate(task_name: &str, tags: &[String]) -> Result<()> {
+    if task_name.trim().is_empty() {
+        return Err(MetronomeError::Validation(String::from(
+            "task names cannot be empty",
+        )));
+    }
+    if let Some(tag) = tags.iter().find(|tag| tag.is_empty() || tag.contains(',')) {
+        return Err(MetronomeError::Validation(format!(
+            "invalid tag \"{}\": tags cannot be empty or contain commas",
+            tag
+        )));
+    }
+    Ok(())
 }
 
-// HELPER FUNCTIONS
+// Name of the user running metronome
+fn current_user() -> String {
+    ["USER", "USERNAME"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|user| !user.is_empty())
+        .unwrap_or_else(|| String::from("unknown"))
+}
 
-fn parse_filter(filter: Filter) -> i64 {
+// Start of the filter's time range, None when every task matches
+fn parse_filter(filter: Filter, now: i64) -> Option<i64> {
     let timedelta = match filter {
         Filter::Day => TimeDelta::days(1),
         Filter::Week => TimeDelta::weeks(1),
@@ -250,265 +1107,174 @@ fn parse_filter(filter: Filter) -> i64 {
     };
 
     if !timedelta.is_zero() {
-        (Local::now() - timedelta).timestamp()
+        Some(now - timedelta.num_seconds())
     } else {
-        0
-    }
-}
-
-fn print_list_rows(mut rows: Rows) -> rusqlite::Result<usize> {
-    // Status does not seem necessary since active tasks will have NULL end times and total times
-    let headers = (
-        "ID",
-        "TASK",
-        "START TIME",
-        "END TIME",
-        "TOTAL TIME",
-        "CATEGORY",
-    );
-
-    println!(
-        "| {:^4} | {:^40} | {:^30} | {:^30} | {:^15} | {:^20} |",
-        headers.0, headers.1, headers.2, headers.3, headers.4, headers.5
-    );
-    println!("{}", "=".repeat(158));
-
-    let mut row_count: usize = 0;
-    while let Some(row) = rows.next()? {
-        row_count += 1;
-        let id: i64 = row.get(0)?;
-        let task: String = row.get(1)?;
-        let start_time_nix: i64 = row.get(2)?;
-        let end_time_nix: Option<i64> = row.get(3)?;
-        let total_time_s: Option<i64> = row.get(4)?;
-        let category: String = row.get(5)?;
-
-        let start_time = DateTime::from_timestamp(start_time_nix, 0)
-            .unwrap()
-            .naive_local()
-            .format("%c")
-            .to_string();
-
-        let end_time = {
-            if end_time_nix.is_some() {
-                DateTime::from_timestamp(end_time_nix.unwrap(), 0)
-                    .unwrap()
-                    .naive_local()
-                    .format("%c")
-                    .to_string()
-            } else {
-                "NULL".to_string()
-            }
-        };
-
-        let total_time = match total_time_s {
-            Some(time_s) => TaskTime::from(time_s).to_string(),
-            None => "NULL".to_string(),
-        };
-
-        println!(
-            "| {:^4} | {:^40} | {:^30} | {:^30} | {:^15} | {:^20} |",
-            id, task, start_time, end_time, total_time, category
-        );
-    }
-
-    Ok(row_count)
-}
-
-fn print_total_time_rows(mut rows: Rows) -> rusqlite::Result<i64> {
-    let headers = ("CATEGORY", "TOTAL TIME", "PERCENTAGE");
-    println!(
-        "| {:^20} | {:^15} | {:^12} |",
-        headers.0, headers.1, headers.2
-    );
-    println!("{}", "=".repeat(57));
-
-    let mut total_time_sum = 0;
-    let mut category_vec = vec![];
-    let mut time_vec = vec![];
-    while let Some(row) = rows.next()? {
-        let category: String = row.get(0)?;
-        let total_time_s: i64 = row.get(1)?;
-        total_time_sum += total_time_s;
-        category_vec.push(category);
-        time_vec.push(total_time_s);
-    }
-
-    for (category, total_time_s) in zip(&category_vec, &time_vec) {
-        // Format from total seconds to h m d format
-        let time_s = total_time_s.clone(); // Avoid borrowing issues
-        let total_time_fmt = TaskTime::from(time_s).to_string();
-        println!(
-            "| {:^20} | {:^15} | {:^12.2} |",
-            category,
-            total_time_fmt,
-            (time_s as f32 / total_time_sum as f32) * 100f32
-        );
+        None
     }
-    println!("{}", "=".repeat(57));
-    println!(
-        "| {:^20} | {:^15} | {:^12.2} |",
-        "TOTAL",
-        TaskTime::from(total_time_sum).to_string(),
-        100f32
-    );
-
-    Ok(total_time_sum)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rusqlite::Connection;
+    use billing::Amount;
+    use chrono::{NaiveTime, Weekday};
+    use clock::FixedClock;
+    use invoice::{InvoiceFormat, InvoiceLine};
+    use store::sqlite::SqliteStore;
     use strum::IntoEnumIterator; // For iterating through Filter enums in testing
+    use timezone::DisplayZone;
 
-    fn setup() -> rusqlite::Result<Connection> {
-        let conn = Connection::open("unit_tests.db")?;
-        create_task_table(&conn)?;
-        Ok(conn)
+    // Mon Apr 22 2024 15:33:20 UTC
+    const NOW: i64 = 1713800000;
+
+    fn setup() -> Result<Tracker> {
+        Ok(setup_with_clock(Config::default())?.0)
+    }
+
+    fn setup_with_clock(config: Config) -> Result<(Tracker, FixedClock)> {
+        // Each test gets its own database so tests can run in parallel
+        let clock = FixedClock::new(NOW);
+        let store = SqliteStore::open_in_memory()?;
+        let tracker = Tracker::new(Box::new(store), config).with_clock(clock.clone());
+        Ok((tracker, clock))
     }
 
-    fn teardown(connection: Connection) {
-        connection
-            .execute("DROP TABLE tasks", ())
-            .expect("Table could not be dropped for teardown");
-        connection
+    fn teardown(tracker: Tracker) {
+        tracker
             .close()
-            .expect("Connection to unit_tests.db was not successfully closed.");
-        std::fs::remove_file("unit_tests.db").expect("Could not remove unit_tests.db");
+            .expect("Connection to the test database was not successfully closed.");
     }
 
-    #[test]
-    fn test_start_task_no_category() -> rusqlite::Result<()> {
-        // Setup
-        let conn = setup()?;
-        let task_name = "test_start_task_no_category".to_string();
-        let expected_category = "Misc".to_string();
+    fn count_active(tracker: &Tracker) -> Result<usize> {
+        Ok(tracker
+            .list(&ListOptions::new(ListMode::Active, Filter::All))?
+            .len())
+    }
 
-        // Add task to table
-        let start_time = Local::now().timestamp();
-        let returned_start_fn = match start_task(&conn, &task_name, None)? {
-            StartTask(start_time) => start_time,
-            _ => panic!("Unexpected enum returned from start_task function."),
-        };
+    #[test]
+    fn test_start_task_no_category() -> Result<()> {
+        let mut tracker = setup()?;
+        let task_name = "test_start_task_no_category";
+        let expected_category = "Misc";
 
-        assert!((start_time - returned_start_fn).abs() <= 1);
+        let Started { task, stopped } = tracker.start(task_name, None, &[])?;
 
-        let mut stmt = conn.prepare("SELECT * from tasks WHERE name = ?1")?;
-        let (returned_name, returned_start, category) =
-            stmt.query_row(params![task_name], |row| {
-                Ok((
-                    row.get::<_, String>(1)?,
-                    row.get::<_, i64>(2)?,
-                    row.get::<_, String>(5)?,
-                ))
-            })?;
-        stmt.finalize()?; // Needed to release the borrowed connection
+        assert_eq!(NOW, task.start_time);
+        assert!(stopped.is_empty());
 
+        // Check the stored row, not just the returned task
+        let stored = tracker.get(task.id)?.expect("task was not stored");
         println!(
             "Set task name: {}, Returned task name: {}",
-            task_name, returned_name
-        );
-        assert_eq!(task_name, returned_name);
-
-        println!(
-            "Expected start time: {}, Returned start time: {}",
-            start_time, returned_start
+            task_name, stored.name
         );
-        assert!((start_time - returned_start).abs() <= 1);
+        assert_eq!(task_name, stored.name);
+        assert_eq!(task.start_time, stored.start_time);
+        assert_eq!(TaskStatus::Active, stored.status);
 
         println!(
             "Expected category: {}, Returned category: {}",
-            expected_category, category
+            expected_category, stored.category
         );
-        assert_eq!(expected_category, category);
+        assert_eq!(expected_category, stored.category);
 
-        teardown(conn);
+        teardown(tracker);
 
         Ok(())
     }
 
     #[test]
-    fn test_start_task_with_category() -> rusqlite::Result<()> {
-        // Setup
-        let conn = setup()?;
-        let task_name = "test_start_task_with_category".to_string();
-        let expected_category = "unit_tests".to_string();
+    fn test_start_task_default_category() -> Result<()> {
+        let mut config = Config::default();
+        config.set("default_category", "Work").unwrap();
+        let (mut tracker, _) = setup_with_clock(config)?;
 
-        // Add task to table
-        let start_time = Local::now().timestamp();
-        let returned_start_fn = match start_task(&conn, &task_name, Some(&expected_category))? {
-            StartTask(start_time) => start_time,
-            _ => panic!("Unexpected enum returned from start_task function."),
-        };
+        let Started { task, .. } = tracker.start("Task_A", None, &[])?;
+        assert_eq!("Work", task.category);
 
-        assert!((start_time - returned_start_fn).abs() <= 1);
+        teardown(tracker);
 
-        let mut stmt = conn.prepare("SELECT * from tasks WHERE name = ?1")?;
-        let (returned_name, returned_start, category) =
-            stmt.query_row(params![task_name], |row| {
-                Ok((
-                    row.get::<_, String>(1)?,
-                    row.get::<_, i64>(2)?,
-                    row.get::<_, String>(5)?,
-                ))
-            })?;
-        stmt.finalize()?; // Needed to release the borrowed connection
+        Ok(())
+    }
 
-        println!(
-            "Set task name: {}, Returned task name: {}",
-            task_name, returned_name
-        );
-        assert_eq!(task_name, returned_name);
+    #[test]
+    fn test_start_task_with_category() -> Result<()> {
+        let mut tracker = setup()?;
+        let task_name = "test_start_task_with_category";
+        let expected_category = "unit_tests";
 
-        println!(
-            "Expected start time: {}, Returned start time: {}",
-            start_time, returned_start
-        );
-        assert!((start_time - returned_start).abs() <= 1);
+        let Started { task, .. } = tracker.start(task_name, Some(expected_category), &[])?;
+
+        assert_eq!(NOW, task.start_time);
+
+        let stored = tracker.get(task.id)?.expect("task was not stored");
+        assert_eq!(task_name, stored.name);
+        assert_eq!(task.start_time, stored.start_time);
 
         println!(
             "Expected category: {}, Returned category: {}",
-            expected_category, category
+            expected_category, stored.category
         );
-        assert_eq!(expected_category, category);
+        assert_eq!(expected_category, stored.category);
 
-        teardown(conn);
+        teardown(tracker);
 
         Ok(())
     }
 
     #[test]
-    fn test_end_task() -> rusqlite::Result<()> {
-        let conn = setup()?;
+    fn test_start_task_single_active() -> Result<()> {
+        let (mut tracker, clock) = setup_with_clock(Config {
+            single_active: true,
+            ..Default::default()
+        })?;
+
+        tracker.start("Task_A", None, &[])?;
+        tracker.start("Task_B", None, &[])?;
+        clock.advance(90);
+        let Started { task, stopped } = tracker.start("Task_C", None, &[])?;
+
+        assert_eq!(1, count_active(&tracker)?);
+        assert_eq!(TaskStatus::Active, task.status);
+
+        // The stopped task ends when the new one starts
+        assert_eq!(1, stopped.len());
+        assert_eq!("Task_B", stopped[0].name);
+        assert_eq!(Some(NOW + 90), stopped[0].end_time);
+        assert_eq!(Some(90), stopped[0].total_time);
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_end_task() -> Result<()> {
+        let (mut tracker, clock) = setup_with_clock(Config::default())?;
 
         // Start tasks to end
         let tasks_to_start = vec!["Task_A", "Task_B", "Task_C"];
         for task in &tasks_to_start {
-            let task = task.to_string();
-            start_task(&conn, &task, None)?; // category unimportant to this test
+            tracker.start(task, None, &[])?; // category unimportant to this test
         }
 
-        // Query the number of active tasks
-        let sql = "SELECT * FROM tasks WHERE status = 'Active'";
-        let mut stmt = conn.prepare(sql)?;
-        let rows_before = stmt.query(())?.count()?;
-
+        let rows_before = count_active(&tracker)?;
         println!("Number of initial active tasks: {}", rows_before);
         assert_eq!(rows_before, tasks_to_start.len());
 
         // Call end task, ensure the number of active tasks are decreasing
         for (i, task) in tasks_to_start.iter().enumerate() {
-            let task = task.to_string();
             let i = i + 1;
             let expected_rows = rows_before - i;
 
-            println!("Calling end_task: Iteration {}", i);
-            end_task(&conn, &task)?;
-
-            let mut stmt = conn.prepare(sql)?;
-            let rows_after = stmt.query(())?.count()?;
+            println!("Calling end: Iteration {}", i);
+            clock.advance(60);
+            let ended = tracker.end(task)?.remove(0);
+            assert_eq!(TaskStatus::Complete, ended.status);
+            assert_eq!(Some(NOW + 60 * i as i64), ended.end_time);
+            assert_eq!(Some(60 * i as i64), ended.total_time);
 
+            let rows_after = count_active(&tracker)?;
             println!(
                 "Expected number of active tasks: {}, Returned number of active tasks: {}",
                 expected_rows, rows_after
@@ -516,278 +1282,1372 @@ mod tests {
             assert_eq!(expected_rows, rows_after);
         }
 
-        stmt.finalize()?;
-        teardown(conn);
+        // An ended task is not ended again, which would overwrite its end time
+        clock.advance(60);
+        assert!(matches!(
+            tracker.end("Task_A"),
+            Err(MetronomeError::NotFound(_))
+        ));
+        let task_a = tracker.get(1)?.expect("task was not stored");
+        assert_eq!(Some(NOW + 60), task_a.end_time);
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_end_errors() -> Result<()> {
+        let mut tracker = setup()?;
+
+        let result = tracker.end("Task_A");
+        assert!(matches!(result, Err(MetronomeError::NotFound(_))));
+        assert_eq!(error::EXIT_NOT_FOUND, result.unwrap_err().exit_code());
+
+        let result = tracker.end_last();
+        assert!(matches!(result, Err(MetronomeError::NotFound(_))));
+
+        // Ending all tasks when none are active is not an error
+        assert!(tracker.end_all()?.is_empty());
+
+        let result = tracker.start(" ", None, &[]);
+        assert!(matches!(result, Err(MetronomeError::Validation(_))));
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_end_same_name() -> Result<()> {
+        let (mut tracker, clock) = setup_with_clock(Config::default())?;
+
+        tracker.start("Task_A", None, &[])?;
+        clock.advance(60);
+        tracker.start("Task_A", None, &[])?;
+        clock.advance(60);
+        tracker.start("Task_A", None, &[])?;
+        clock.advance(60);
+
+        // Ending the last task leaves the others of its name running
+        let last = tracker.end_last()?;
+        assert_eq!(3, last.id);
+        assert_eq!(2, count_active(&tracker)?);
+
+        // Ending by name ends all of them, oldest first
+        clock.advance(60);
+        let ended = tracker.end("Task_A")?;
+        assert_eq!(
+            vec![1, 2],
+            ended.iter().map(|task| task.id).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(240), ended[0].total_time);
+        assert_eq!(Some(180), ended[1].total_time);
+        assert_eq!(0, count_active(&tracker)?);
+
+        teardown(tracker);
 
         Ok(())
     }
 
     #[test]
-    fn test_end_all_active() -> rusqlite::Result<()> {
-        let conn = setup()?;
+    fn test_end_all_active() -> Result<()> {
+        let mut tracker = setup()?;
 
         // Start tasks to end
         let tasks_to_start = vec!["Task_A", "Task_B", "Task_C"];
         for task in &tasks_to_start {
-            let task = task.to_string();
-            start_task(&conn, &task, None)?; // category unimportant to this test
+            tracker.start(task, None, &[])?; // category unimportant to this test
         }
 
-        // Query the number of active tasks
-        let sql = "SELECT * FROM tasks WHERE status = 'Active'";
-        let mut stmt = conn.prepare(sql)?;
-        let rows_before = stmt.query(())?.count()?;
-        stmt.finalize()?; // Free the connection
-
+        let rows_before = count_active(&tracker)?;
         println!("Number of active tasks: {}", rows_before);
         assert_eq!(rows_before, tasks_to_start.len());
 
         // End all active tasks
-        let num_ended = match end_all_active(&conn)? {
-            EndAllActive(ended) => ended,
-            _ => panic!("Unexpected enum returned from end_all_active call."),
-        };
-
-        assert_eq!(rows_before, num_ended);
+        let ended = tracker.end_all()?;
+        assert_eq!(rows_before, ended.len());
 
         // Ensure there are no active tasks remaining
-        let mut stmt = conn.prepare(sql)?;
-        let rows_after = stmt.query(())?.count()?;
-        stmt.finalize()?;
-
+        let rows_after = count_active(&tracker)?;
         println!(
             "Expected number of active tasks: {}, Returned number of active tasks: {}",
             0, rows_after
         );
         assert_eq!(0, rows_after);
 
-        teardown(conn);
+        teardown(tracker);
 
         Ok(())
     }
 
     #[test]
-    fn test_list_active() -> rusqlite::Result<()> {
-        let conn = setup()?;
+    fn test_switch_task() -> Result<()> {
+        let mut tracker = setup()?;
 
-        // Create active tasks
-        let tasks_to_start = vec!["Task_A", "Task_B", "Task_C", "Task_D", "Task_E"];
-        for task in &tasks_to_start {
-            let task = task.to_string();
-            // Ensure category is correct in output
-            let category = format!("Category_{}", task);
-            start_task(&conn, &task, Some(&category))?;
-        }
+        tracker.start("Task_A", None, &[])?;
+        tracker.start("Task_B", None, &[])?;
 
-        end_task(&conn, &String::from("Task_B"))?;
-        end_task(&conn, &String::from("Task_D"))?;
+        let category = "Category_C";
+        let Started { task, stopped } = tracker.switch("Task_C", Some(category), &[])?;
+        assert_eq!(2, stopped.len());
 
-        let expected_active = tasks_to_start.len() - 2;
-        let num_active = match list_active(&conn, Filter::All)? {
-            List(active) => active,
-            _ => panic!("Unexpected enum returned from list_active call."),
-        };
+        // Ended tasks finish exactly when the new task starts
+        for ended in &stopped {
+            assert_eq!(Some(task.start_time), ended.end_time);
+        }
 
-        println!(
-            "Expected active tasks: {} Returned active tasks: {}",
-            expected_active, num_active
-        );
-        assert_eq!(expected_active, num_active);
+        let active = tracker.list(&ListOptions::new(ListMode::Active, Filter::All))?;
+        assert_eq!(vec![task], active);
+        assert_eq!("Task_C", active[0].name);
+        assert_eq!(category, active[0].category);
 
-        teardown(conn);
+        teardown(tracker);
 
         Ok(())
     }
 
     #[test]
-    fn test_list_complete() -> rusqlite::Result<()> {
-        let conn = setup()?;
+    fn test_continue_task() -> Result<()> {
+        let mut tracker = setup()?;
+
+        // Nothing to continue yet
+        assert!(matches!(
+            tracker.continue_task(None),
+            Err(MetronomeError::NotFound(_))
+        ));
+
+        let category = "Category_A";
+        let tags = vec![String::from("billable"), String::from("meeting")];
+        tracker.start("Task_A", Some(category), &tags)?;
+        tracker.start("Task_B", None, &[])?;
+
+        // Still active, so it is not restarted
+        assert!(matches!(
+            tracker.continue_task(Some("Task_A")),
+            Err(MetronomeError::Conflict(_))
+        ));
+
+        tracker.end("Task_A")?;
+        let Started { task, .. } = tracker.continue_task(None)?;
+
+        assert_eq!("Task_A", task.name);
+        assert_eq!(category, task.category);
+        assert_eq!(tags, task.tags);
+        assert_eq!(TaskStatus::Active, task.status);
+
+        // Unknown IDs and names are reported
+        assert!(matches!(
+            tracker.continue_task(Some("999")),
+            Err(MetronomeError::NotFound(_))
+        ));
+        assert!(matches!(
+            tracker.continue_task(Some("Task_Z")),
+            Err(MetronomeError::NotFound(_))
+        ));
+
+        teardown(tracker);
 
+        Ok(())
+    }
+
+    fn start_list_tasks(tracker: &mut Tracker) -> Result<usize> {
         // Create active tasks
         let tasks_to_start = vec!["Task_A", "Task_B", "Task_C", "Task_D", "Task_E"];
         for task in &tasks_to_start {
-            let task = task.to_string();
             // Ensure category is correct in output
             let category = format!("Category_{}", task);
-            start_task(&conn, &task, Some(&category))?;
+            tracker.start(task, Some(&category), &[])?;
         }
 
         // Complete 2 tasks
-        end_task(&conn, &String::from("Task_B"))?;
-        end_task(&conn, &String::from("Task_D"))?;
+        tracker.end("Task_B")?;
+        tracker.end("Task_D")?;
+
+        Ok(tasks_to_start.len())
+    }
+
+    #[test]
+    fn test_list_active() -> Result<()> {
+        let mut tracker = setup()?;
+        let num_started = start_list_tasks(&mut tracker)?;
+
+        let expected_active = num_started - 2;
+        let active = tracker.list(&ListOptions::new(ListMode::Active, Filter::All))?;
+
+        println!(
+            "Expected active tasks: {} Returned active tasks: {}",
+            expected_active,
+            active.len()
+        );
+        assert_eq!(expected_active, active.len());
+        for task in &active {
+            assert_eq!(format!("Category_{}", task.name), task.category);
+        }
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_complete() -> Result<()> {
+        let mut tracker = setup()?;
+        start_list_tasks(&mut tracker)?;
 
         let expected_complete = 2usize;
-        let num_complete = match list_complete(&conn, Filter::All)? {
-            List(comlete) => comlete,
-            _ => panic!("Unexpected enum returned from list_all call."),
-        };
+        let complete = tracker.list(&ListOptions::new(ListMode::Complete, Filter::All))?;
 
         println!(
             "Expected complete tasks {}, Returned complete tasks {}",
-            expected_complete, num_complete
+            expected_complete,
+            complete.len()
         );
-        assert_eq!(expected_complete, num_complete);
+        assert_eq!(expected_complete, complete.len());
 
-        teardown(conn);
+        teardown(tracker);
 
         Ok(())
     }
 
     #[test]
-    fn test_list_all() -> rusqlite::Result<()> {
-        let conn = setup()?;
+    fn test_list_pages() -> Result<()> {
+        let mut tracker = setup()?;
+        start_list_tasks(&mut tracker)?;
+
+        let page = tracker.list(&ListOptions {
+            limit: Some(2),
+            offset: 1,
+            ..Default::default()
+        })?;
+        assert_eq!(vec!["Task_B", "Task_C"], names(&page));
+
+        let page = tracker.list(&ListOptions {
+            limit: Some(2),
+            descending: true,
+            ..Default::default()
+        })?;
+        assert_eq!(vec!["Task_E", "Task_D"], names(&page));
+
+        let page = tracker.list(&ListOptions {
+            offset: 10,
+            ..Default::default()
+        })?;
+        assert!(page.is_empty());
+
+        teardown(tracker);
 
-        // Create active tasks
-        let tasks_to_start = vec!["Task_A", "Task_B", "Task_C", "Task_D", "Task_E"];
-        for task in &tasks_to_start {
-            let task = task.to_string();
-            let category = format!("Category_{}", task);
-            start_task(&conn, &task, Some(&category))?;
-        }
+        Ok(())
+    }
 
-        // Complete 2 tasks
-        end_task(&conn, &String::from("Task_B"))?;
-        end_task(&conn, &String::from("Task_D"))?;
+    #[test]
+    fn test_list_sorted() -> Result<()> {
+        let mut tracker = setup()?;
+
+        add_completed_task(&mut tracker, "Task_B", NOW - 300, 120, "Category_A")?;
+        add_completed_task(&mut tracker, "Task_C", NOW - 600, 60, "Category_B")?;
+        tracker.start("Task_A", Some("Category_C"), &[])?;
+
+        let sorted = |tracker: &Tracker, sort: SortKey, descending: bool| {
+            let options = ListOptions {
+                sort,
+                descending,
+                ..Default::default()
+            };
+            tracker.list(&options).map(|tasks| names(&tasks))
+        };
+        assert_eq!(
+            vec!["Task_C", "Task_B", "Task_A"],
+            sorted(&tracker, SortKey::Start, false)?
+        );
+        assert_eq!(
+            vec!["Task_A", "Task_B", "Task_C"],
+            sorted(&tracker, SortKey::Name, false)?
+        );
+        assert_eq!(
+            vec!["Task_A", "Task_C", "Task_B"],
+            sorted(&tracker, SortKey::Category, true)?
+        );
+        // The active task has no duration yet, so it comes last either way
+        assert_eq!(
+            vec!["Task_C", "Task_B", "Task_A"],
+            sorted(&tracker, SortKey::Duration, false)?
+        );
+        assert_eq!(
+            vec!["Task_B", "Task_C", "Task_A"],
+            sorted(&tracker, SortKey::Duration, true)?
+        );
+        assert_eq!(
+            vec!["Task_B", "Task_C", "Task_A"],
+            sorted(&tracker, SortKey::End, true)?
+        );
+
+        teardown(tracker);
 
-        let num_tasks = match list_all(&conn, Filter::All)? {
-            List(all_tasks) => all_tasks,
-            _ => panic!("Unexpected enum returned from list_all call."),
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_filtered() -> Result<()> {
+        let (mut tracker, clock) = setup_with_clock(Config::default())?;
+
+        add_completed_task(&mut tracker, "fix-1", NOW - 7200, 300, "dev")?;
+        add_completed_task(&mut tracker, "fix-22", NOW - 3600, 3600, "dev")?;
+        add_completed_task(&mut tracker, "standup", NOW - 1800, 900, "admin")?;
+        tracker.start("review", Some("dev"), &[])?;
+        clock.advance(1200);
+
+        let filtered = |tracker: &Tracker, options: ListOptions| {
+            tracker.list(&options).map(|tasks| names(&tasks))
         };
+        assert_eq!(
+            vec!["fix-1", "fix-22", "review"],
+            filtered(
+                &tracker,
+                ListOptions {
+                    excluded_categories: vec![String::from("admin")],
+                    ..Default::default()
+                }
+            )?
+        );
+        assert_eq!(
+            vec!["fix-1", "fix-22"],
+            filtered(
+                &tracker,
+                ListOptions {
+                    name: Some("fix-*".parse().unwrap()),
+                    ..Default::default()
+                }
+            )?
+        );
+        assert_eq!(
+            vec!["fix-22"],
+            filtered(
+                &tracker,
+                ListOptions {
+                    name: Some(r"/^fix-\d{2}$/".parse().unwrap()),
+                    ..Default::default()
+                }
+            )?
+        );
+        // The active task has run for 20 minutes
+        let minutes = |duration: &str| duration.parse::<TaskTime>().unwrap().total_seconds();
+        assert_eq!(
+            vec!["standup", "review"],
+            filtered(
+                &tracker,
+                ListOptions {
+                    min_duration: Some(minutes("10m")),
+                    max_duration: Some(minutes("30m")),
+                    ..Default::default()
+                }
+            )?
+        );
+        assert_eq!(
+            vec!["review"],
+            filtered(
+                &tracker,
+                ListOptions {
+                    categories: vec![String::from("dev")],
+                    min_duration: Some(minutes("15m")),
+                    ..ListOptions::new(ListMode::Active, Filter::All)
+                }
+            )?
+        );
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_all() -> Result<()> {
+        let mut tracker = setup()?;
+        let num_started = start_list_tasks(&mut tracker)?;
+
+        let all_tasks = tracker.list(&ListOptions::new(ListMode::All, Filter::All))?;
 
         println!(
             "Expected total tasks: {}, Returned total tasks: {}",
-            tasks_to_start.len(),
-            num_tasks
+            num_started,
+            all_tasks.len()
         );
-        assert_eq!(tasks_to_start.len(), num_tasks);
+        assert_eq!(num_started, all_tasks.len());
+
+        teardown(tracker);
+
+        Ok(())
+    }
 
-        teardown(conn);
+    fn add_completed_task(
+        tracker: &mut Tracker,
+        task_name: &str,
+        start_time: i64,
+        duration: i64,
+        category: &str,
+    ) -> Result<()> {
+        add_tagged_task(tracker, task_name, start_time, duration, category, &[])
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    // The start of a day in UTC, the zone of the tests that count whole days
+    fn midnight(day: &str) -> i64 {
+        date(day).and_time(NaiveTime::MIN).and_utc().timestamp()
+    }
 
+    fn add_tagged_task(
+        tracker: &mut Tracker,
+        task_name: &str,
+        start_time: i64,
+        duration: i64,
+        category: &str,
+        tags: &[&str],
+    ) -> Result<()> {
+        tracker.store.insert(Task {
+            id: 0,
+            name: String::from(task_name),
+            start_time,
+            end_time: Some(start_time + duration),
+            total_time: Some(duration),
+            category: String::from(category),
+            status: TaskStatus::Complete,
+            tags: tags.iter().map(|tag| String::from(*tag)).collect(),
+            utc_offset: None,
+            uuid: String::new(),
+            modified_at: start_time,
+            invoice: None,
+        })?;
         Ok(())
     }
 
     #[test]
-    fn test_sum_task_times() -> rusqlite::Result<()> {
-        // Not sure exactly what to test here.
-        // Think confirmation of the enum returned should be fine as a first pass
-        let conn = setup()?;
+    fn test_sum_task_times() -> Result<()> {
+        let mut tracker = setup()?;
+
+        add_completed_task(&mut tracker, "Task A", NOW, 300, "Category A")?;
+        add_completed_task(&mut tracker, "Task B", NOW, 65, "Category B")?;
+        add_completed_task(&mut tracker, "Task C", NOW, 1800, "Category A")?;
+        add_completed_task(&mut tracker, "Task D", NOW, 4500, "Category B")?;
+        add_completed_task(&mut tracker, "Misc Task", NOW, 600, "Misc")?;
+
+        let totals = tracker.totals(Filter::All, None)?;
+
+        assert_eq!((300 + 65 + 1800 + 4500 + 600), totals.time.total_seconds());
+        // Largest category first
+        let categories: Vec<(&str, i64)> = totals
+            .categories
+            .iter()
+            .map(|total| (total.category.as_str(), total.time.total_seconds()))
+            .collect();
+        assert_eq!(
+            vec![("Category B", 4565), ("Category A", 2100), ("Misc", 600)],
+            categories
+        );
+        let percentages: f32 = totals
+            .categories
+            .iter()
+            .map(|category| totals.percentage(category))
+            .sum();
+        assert!((100.0 - percentages).abs() < 0.01);
 
-        let mut stmt = conn.prepare(
-            "INSERT INTO tasks \
-        (name, start_time, end_time, total_time, category, status) VALUES \
-        (?1, ?2, ?3, ?4, ?5, 'Complete')",
-        )?;
-        fn add_completed_tasks(
-            stmt: &mut Statement,
-            task_name: &str,
-            duration: i64,
-            category: &str,
-        ) -> rusqlite::Result<()> {
-            let start_time = Local::now().timestamp();
-            let _ = stmt.execute(params![
-                task_name,
-                start_time,
-                start_time + duration,
-                duration,
-                category
-            ])?;
-            Ok(())
-        }
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rounded_totals() -> Result<()> {
+        let mut config = Config {
+            timezone: "UTC".parse().unwrap(),
+            ..Default::default()
+        };
+        config.set("rounding", "up 15m").unwrap();
+        config
+            .set("category.Support.rounding", "nearest 6m per day")
+            .unwrap();
+        config.set("category.Internal.rounding", "none").unwrap();
+        assert_eq!(
+            Some(String::from("nearest 6m per day")),
+            config.get("category.Support.rounding")
+        );
+        let (mut tracker, _) = setup_with_clock(config)?;
+
+        // Each task is rounded up on its own
+        add_completed_task(&mut tracker, "Task A", NOW - 3600, 300, "Dev")?;
+        add_completed_task(&mut tracker, "Task B", NOW - 1800, 1000, "Dev")?;
+        // 200 seconds on one day rounds up to 6 minutes, 170 seconds on the day before to none,
+        // where rounding each task would have billed nothing at all
+        add_completed_task(&mut tracker, "Task C", NOW - 3600, 100, "Support")?;
+        add_completed_task(&mut tracker, "Task D", NOW - 1800, 100, "Support")?;
+        add_completed_task(&mut tracker, "Task E", NOW - 86400, 170, "Support")?;
+        add_completed_task(&mut tracker, "Task F", NOW, 1234, "Internal")?;
+        tracker.start("Task G", Some("Dev"), &[])?;
+
+        let totals = tracker.totals(Filter::All, None)?;
+        let categories: Vec<(&str, i64, i64)> = totals
+            .categories
+            .iter()
+            .map(|total| {
+                (
+                    total.category.as_str(),
+                    total.time.total_seconds(),
+                    total.rounded.total_seconds(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("Dev", 1300, 2700),
+                ("Internal", 1234, 1234),
+                ("Support", 370, 360)
+            ],
+            categories
+        );
+        assert_eq!(1300 + 1234 + 370, totals.time.total_seconds());
+        assert_eq!(2700 + 1234 + 360, totals.rounded.total_seconds());
+        // Percentages are shares of the billed time
+        assert!((totals.percentage(&totals.categories[0]) - 2700.0 / 4294.0 * 100.0).abs() < 0.01);
 
-        add_completed_tasks(&mut stmt, "Task A", 300, "Category A")?;
-        add_completed_tasks(&mut stmt, "Task B", 65, "Category B")?;
-        add_completed_tasks(&mut stmt, "Task C", 1800, "Category A")?;
-        add_completed_tasks(&mut stmt, "Task D", 4500, "Category B")?;
-        add_completed_tasks(&mut stmt, "Misc Task", 600, "Misc")?;
+        teardown(tracker);
 
-        stmt.finalize()?;
+        Ok(())
+    }
 
-        let SumTaskTimes(total_time) = sum_task_times(&conn, Filter::All, None)? else {
-            panic!("SumTaskTimes enum was not returned.")
+    #[test]
+    fn test_billed_totals() -> Result<()> {
+        let mut config = Config::default();
+        for (key, value) in [
+            ("rate", "100"),
+            ("currency", "EUR"),
+            ("category.Support.rate", "60"),
+            ("category.Support.rounding", "up 15m per task"),
+            ("category.Internal.billable", "false"),
+            ("tag.acme.rate", "150"),
+            ("tag.acme.currency", "USD"),
+            ("tag.free.billable", "false"),
+        ] {
+            config.set(key, value).unwrap();
+            assert_eq!(Some(String::from(value)), config.get(key));
+        }
+        assert!(config.set("tag.acme.rounding", "up 15m").is_err());
+        // An invalid value leaves no empty group behind
+        assert!(config.set("tag.other.rate", "lots").is_err());
+        assert!(!config.tags.contains_key("other"));
+        let (mut tracker, _) = setup_with_clock(config)?;
+
+        add_completed_task(&mut tracker, "Task A", NOW - 7200, 1800, "Dev")?;
+        // Tags take precedence over the category and the defaults
+        add_tagged_task(&mut tracker, "Task B", NOW - 3600, 3600, "Dev", &["acme"])?;
+        add_tagged_task(&mut tracker, "Task C", NOW - 600, 900, "Dev", &["free"])?;
+        // 10 minutes are billed as 15
+        add_completed_task(&mut tracker, "Task D", NOW - 3600, 600, "Support")?;
+        add_completed_task(&mut tracker, "Task E", NOW - 3600, 1200, "Internal")?;
+
+        let amount = |value: f64, currency: &str| Amount {
+            value,
+            currency: String::from(currency),
         };
+        let totals = tracker.billed_totals(Filter::All, None)?;
+        let billed: Vec<(&str, Billed)> = totals
+            .categories
+            .iter()
+            .map(|total| (total.category.as_str(), total.billed.clone().unwrap()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "Dev",
+                    Billed {
+                        time: TaskTime::from(5400),
+                        amounts: vec![amount(50.0, "EUR"), amount(150.0, "USD")]
+                    }
+                ),
+                ("Internal", Billed::default()),
+                (
+                    "Support",
+                    Billed {
+                        time: TaskTime::from(900),
+                        amounts: vec![amount(15.0, "EUR")]
+                    }
+                ),
+            ],
+            billed
+        );
+        assert_eq!(
+            Some(Billed {
+                time: TaskTime::from(6300),
+                amounts: vec![amount(65.0, "EUR"), amount(150.0, "USD")]
+            }),
+            totals.billed
+        );
+        assert_eq!("65.00 EUR", totals.billed.unwrap().amounts[0].to_string());
 
-        assert_eq!((300 + 65 + 1800 + 4500 + 600), total_time);
+        // Plain totals leave billing out
+        assert_eq!(None, tracker.totals(Filter::All, None)?.billed);
 
-        teardown(conn);
+        teardown(tracker);
 
         Ok(())
     }
 
-    fn filter_test_helper(connection: &Connection) -> rusqlite::Result<()> {
-        let mut stmt = connection.prepare(
-            "INSERT INTO tasks \
-        (name, start_time, category, status) VALUES \
-        (?1, ?2, ?3, 'Active')",
-        )?;
-        fn add_active_tasks(
-            stmt: &mut Statement,
-            task_name: &str,
-            start_time: i64,
-            category: &str,
-        ) -> rusqlite::Result<()> {
-            let _ = stmt.execute(params![task_name, start_time, category])?;
-            Ok(())
+    // Description, billed seconds and amount of an invoice line
+    type InvoiceRow<'a> = (&'a str, i64, f64);
+
+    fn invoice_row(line: &InvoiceLine) -> InvoiceRow<'_> {
+        (
+            line.description.as_str(),
+            line.time.total_seconds(),
+            line.amount,
+        )
+    }
+
+    #[test]
+    fn test_invoice() -> Result<()> {
+        let mut config = Config {
+            timezone: "UTC".parse().unwrap(),
+            ..Default::default()
+        };
+        for (key, value) in [
+            ("rate", "100"),
+            ("currency", "EUR"),
+            ("category.Acme.rounding", "up 15m per task"),
+            ("category.Acme.tax", "19"),
+            ("category.Beta.rounding", "up 1h per day"),
+            ("tag.free.billable", "false"),
+            ("tag.usd.currency", "USD"),
+        ] {
+            config.set(key, value).unwrap();
         }
+        assert!(config.set("tax", "120").is_err());
+        assert!(config.set("tag.usd.tax", "7").is_err());
+        let (mut tracker, _) = setup_with_clock(config)?;
+
+        // 50 minutes are billed as an hour
+        add_completed_task(&mut tracker, "Task A", NOW - 86400, 3000, "Acme")?;
+        add_completed_task(&mut tracker, "Task B", NOW - 3600, 1800, "Acme")?;
+        add_tagged_task(&mut tracker, "Task C", NOW - 1800, 600, "Acme", &["free"])?;
+        add_completed_task(&mut tracker, "Task D", NOW - 3600, 600, "Other")?;
+        add_completed_task(&mut tracker, "Task E", NOW - 10 * 86400, 900, "Acme")?;
+        tracker.start("Task F", Some("Acme"), &[])?;
+
+        assert!(tracker
+            .invoice("Acme", date("2024-04-22"), date("2024-04-15"))
+            .is_err());
+        let invoice = tracker.invoice("Acme", date("2024-04-15"), date("2024-04-22"))?;
+        assert_eq!(1, invoice.number);
+        assert_eq!(date("2024-04-22"), invoice.date);
+        assert_eq!("EUR", invoice.currency);
+        let days: Vec<(NaiveDate, Vec<InvoiceRow>)> = invoice
+            .days
+            .iter()
+            .map(|day| (day.date, day.lines.iter().map(invoice_row).collect()))
+            .collect();
+        assert_eq!(
+            vec![
+                (date("2024-04-21"), vec![("Task A", 3600, 100.0)]),
+                (date("2024-04-22"), vec![("Task B", 1800, 50.0)]),
+            ],
+            days
+        );
+        assert_eq!(150.0, invoice.subtotal());
+        assert_eq!(28.5, invoice.tax());
+        assert_eq!(178.5, invoice.total());
+
+        let markdown = invoice.render(InvoiceFormat::Markdown);
+        assert!(markdown.starts_with("# Invoice 1\n"));
+        assert!(markdown.contains("\n## 2024-04-21\n"));
+        assert!(markdown.contains("\n| Task B | 0.50 | 100.00 EUR | 50.00 EUR |\n"));
+        assert!(markdown.contains("\n| **Tax (19%)** | **28.50 EUR** |\n"));
+        assert!(invoice.render(InvoiceFormat::Html).contains(
+            "<tr><td>Task A</td><td>1.00</td><td>100.00 EUR</td><td>100.00 EUR</td></tr>"
+        ));
+        assert!(invoice
+            .render(InvoiceFormat::Text)
+            .contains("\n  Task A                           1.00     100.00 EUR     100.00 EUR\n"));
+
+        // The billed tasks are marked, the rest are left for later
+        let invoiced: Vec<(String, Option<i64>)> = tracker
+            .list(&ListOptions::default())?
+            .into_iter()
+            .map(|task| (task.name, task.invoice))
+            .collect();
+        assert_eq!(
+            vec![
+                (String::from("Task E"), None),
+                (String::from("Task A"), Some(1)),
+                (String::from("Task B"), Some(1)),
+                (String::from("Task D"), None),
+                (String::from("Task C"), None),
+                (String::from("Task F"), None),
+            ],
+            invoiced
+        );
+        assert!(matches!(
+            tracker.invoice("Acme", date("2024-04-15"), date("2024-04-22")),
+            Err(MetronomeError::NotFound(_))
+        ));
+
+        // Nothing is marked when an invoice cannot be made
+        add_tagged_task(&mut tracker, "Task G", NOW - 7200, 600, "Acme", &["usd"])?;
+        assert!(matches!(
+            tracker.invoice("Acme", date("2024-04-01"), date("2024-04-22")),
+            Err(MetronomeError::Validation(_))
+        ));
+        assert_eq!(None, tracker.get(5)?.unwrap().invoice);
+
+        // Rounding per day gets a line of its own, and numbers keep counting up
+        add_completed_task(&mut tracker, "Task H", NOW - 7200, 1800, "Beta")?;
+        add_completed_task(&mut tracker, "Task I", NOW - 3600, 900, "Beta")?;
+        let invoice = tracker.invoice("Beta", date("2024-04-22"), date("2024-04-22"))?;
+        assert_eq!(2, invoice.number);
+        let lines: Vec<InvoiceRow> = invoice.lines().map(invoice_row).collect();
+        assert_eq!(
+            vec![
+                ("Task H", 1800, 50.0),
+                ("Task I", 900, 25.0),
+                ("Rounding (up 1h0m per day)", 900, 25.0),
+            ],
+            lines
+        );
+        assert_eq!(0.0, invoice.tax());
+        assert_eq!(100.0, invoice.total());
 
-        let now = Local::now();
+        teardown(tracker);
 
-        add_active_tasks(&mut stmt, "First Task", 10, "Misc")?;
-        add_active_tasks(
-            &mut stmt,
-            "Over a Year",
-            (now - TimeDelta::days(400)).timestamp(),
-            "Category A",
-        )?;
-        add_active_tasks(
-            &mut stmt,
-            "Within a Year",
-            (now - TimeDelta::days(300)).timestamp(),
-            "Category B",
-        )?;
-        add_active_tasks(
-            &mut stmt,
-            "SemiAnnual",
-            (now - TimeDelta::weeks(23)).timestamp(),
+        Ok(())
+    }
+
+    #[test]
+    fn test_status() -> Result<()> {
+        let (mut tracker, clock) = setup_with_clock(Config {
+            timezone: "UTC".parse().unwrap(),
+            week_start: Weekday::Sun,
+            ..Default::default()
+        })?;
+
+        assert!(tracker.status()?.active.is_empty());
+
+        clock.set(NOW - 3600);
+        tracker.start("Task_A", None, &[])?;
+        tracker.start("Task_B", None, &[])?;
+        clock.advance(600);
+        tracker.end("Task_B")?;
+        tracker.start("Task_C", None, &[])?;
+
+        // Only the time since midnight counts toward today's total, and NOW is a Monday
+        let midnight = NOW - NOW % 86400;
+        add_completed_task(&mut tracker, "Task_D", NOW - 86400, 600, "Misc")?;
+        add_completed_task(&mut tracker, "Task_E", NOW - 2 * 86400, 900, "Misc")?;
+        // Running across midnight, and ended across the start of the week
+        clock.set(midnight - 1800);
+        tracker.start("Task_F", None, &[])?;
+        add_completed_task(
+            &mut tracker,
+            "Task_G",
+            midnight - 86400 - 1800,
+            3600,
             "Misc",
         )?;
-        add_active_tasks(
-            &mut stmt,
-            "Quarter",
-            (now - TimeDelta::weeks(10)).timestamp(),
-            "Category A",
-        )?;
-        add_active_tasks(
-            &mut stmt,
-            "Month",
-            (now - TimeDelta::days(25)).timestamp(),
-            "Category B",
-        )?;
-        add_active_tasks(
-            &mut stmt,
-            "Week",
-            (now - TimeDelta::days(5)).timestamp(),
-            "Misc",
+
+        clock.set(NOW);
+        let status = tracker.status()?;
+
+        assert_eq!(3, status.active.len());
+        assert_eq!("Task_F", status.active[0].name);
+        assert_eq!(3600, status.active[1].elapsed(status.now));
+        assert_eq!(3000, status.active[2].elapsed(status.now));
+        let since_midnight = NOW - midnight;
+        assert_eq!(
+            3600 + 600 + 3000 + since_midnight,
+            status.today.total_seconds()
+        );
+        assert_eq!(
+            3600 + 600 + 3000 + since_midnight + 600 + 1800 + 1800,
+            status.week.total_seconds()
+        );
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prompt() -> Result<()> {
+        let mut tracker = setup()?;
+
+        assert_eq!(None, tracker.prompt_task()?);
+
+        tracker.start("Task_A", None, &[])?;
+        tracker.start("Task_B", Some("Category B"), &[])?;
+        let task = tracker
+            .prompt_task()?
+            .expect("an active task was not found");
+        assert_eq!("Task_B", task.name);
+        assert_eq!(2, task.active_count);
+
+        let task = PromptTask {
+            name: String::from("Task_A"),
+            category: String::from("Category A"),
+            start_time: 1000,
+            active_count: 2,
+        };
+        let rendered = prompt::render(
+            "[{category}] {name} {elapsed:short} ({count}) {unknown}",
+            &task,
+            1000 + 3900,
+            &DisplayZone::default(),
+        );
+        assert_eq!("[Category A] Task_A 1h5m (2) {unknown}", rendered);
+        assert_eq!(
+            "Task_A 1h 5m 0s",
+            prompt::render("{name} {elapsed}", &task, 1000 + 3900, &DisplayZone::Local)
+        );
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_zone() -> Result<()> {
+        let mut tracker = setup()?;
+
+        // Start times are stored with the UTC offset they were recorded in
+        let Started { task, .. } = tracker.start("Task_A", None, &[])?;
+        assert_eq!(
+            Some(timezone::local_offset(task.start_time)),
+            task.utc_offset
+        );
+
+        // 2024-03-31 00:30 UTC is 00:30 in London, before clocks go forward to BST
+        let zone: DisplayZone = "Europe/London".parse().unwrap();
+        assert_eq!("00:30", zone.format(1711845000, None, "%H:%M"));
+        assert_eq!(1711843200, zone.start_of_day(1711845000));
+        // By 23:30 UTC the same day London is on BST, so its next day began at 23:00 UTC
+        assert_eq!("00:30", zone.format(1711927800, None, "%H:%M"));
+        assert_eq!(1711926000, zone.start_of_day(1711927800));
+
+        let zone: DisplayZone = "+05:30".parse().unwrap();
+        assert_eq!("06:00", zone.format(1711845000, None, "%H:%M"));
+        assert_eq!(
+            "02:30",
+            DisplayZone::Recorded.format(1711845000, Some(7200), "%H:%M")
+        );
+        assert!("Mars/Olympus_Mons".parse::<DisplayZone>().is_err());
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync() -> Result<()> {
+        let (mut tracker, clock) = setup_with_clock(Config::default())?;
+        let mut other = Tracker::open_in_memory(Config::default()).with_clock(clock.clone());
+
+        tracker.start("Task_A", None, &[])?;
+        let Started { task: task_b, .. } = other.start("Task_B", None, &[])?;
+        let report = tracker.sync(other.store.as_mut())?;
+        assert_eq!(vec![String::from("Task_A")], names(&report.pushed));
+        assert_eq!(vec![String::from("Task_B")], names(&report.pulled));
+        assert_eq!(task_b.uuid, report.pulled[0].uuid);
+        assert!(report.conflicts.is_empty());
+        // Both stores record the sync in the history of the tasks it copied into them
+        let commands = |tracker: &Tracker, id: i64| -> Result<Vec<String>> {
+            let history = tracker.history(id)?;
+            Ok(history.into_iter().map(|change| change.command).collect())
+        };
+        assert_eq!(vec!["sync"], commands(&tracker, 2)?);
+        assert_eq!(vec!["sync"], commands(&other, 2)?);
+
+        // Syncing again changes nothing, so tasks are never duplicated
+        assert_eq!(SyncReport::default(), tracker.sync(other.store.as_mut())?);
+
+        // Both sides end Task_A, the later change wins
+        clock.advance(60);
+        tracker.end("Task_A")?;
+        clock.advance(60);
+        other.end("Task_A")?;
+        let report = tracker.sync(other.store.as_mut())?;
+        assert_eq!(1, report.conflicts.len());
+        assert!(report.conflicts[0].kept_remote());
+
+        let local = tracker.list(&ListOptions::new(ListMode::All, Filter::All))?;
+        assert_eq!(Some(120), local[0].total_time);
+        let remote = other.list(&ListOptions::new(ListMode::All, Filter::All))?;
+        assert_eq!(2, remote.len());
+        for (local, remote) in local.iter().zip(remote.iter().rev()) {
+            assert_eq!(local.uuid, remote.uuid);
+            assert_eq!(local.total_time, remote.total_time);
+        }
+
+        // The local change wins this time, and is recorded in the other store
+        other.end("Task_B")?;
+        clock.advance(60);
+        tracker.end("Task_B")?;
+        let report = tracker.sync(other.store.as_mut())?;
+        assert!(!report.conflicts[0].kept_remote());
+        assert_eq!(vec!["start", "end", "sync"], commands(&other, 1)?);
+        let change = other.history(1)?.pop().unwrap();
+        assert_eq!(Some(NOW + 120), change.old.and_then(|old| old.end_time));
+        assert_eq!(Some(NOW + 180), change.new.end_time);
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock() -> Result<()> {
+        let config = Config {
+            timezone: "UTC".parse().unwrap(),
+            ..Default::default()
+        };
+        let (mut tracker, clock) = setup_with_clock(config.clone())?;
+        let mut other = Tracker::open_in_memory(config).with_clock(clock.clone());
+
+        // Started the day before the lock and still running
+        clock.set(NOW - 86400);
+        tracker.start("Task_A", None, &[])?;
+        other.start("Task_B", None, &[])?;
+        clock.set(NOW);
+        tracker.start("Task_C", None, &[])?;
+        assert_eq!(None, tracker.locked_until()?);
+        tracker.lock(date("2024-04-22"))?;
+        assert_eq!(Some(midnight("2024-04-22")), tracker.locked_until()?);
+
+        for result in [
+            tracker.end("Task_A").map(|_| ()),
+            tracker.end_all().map(|_| ()),
+            tracker.switch("Task_D", None, &[]).map(|_| ()),
+            tracker.sync(other.store.as_mut()).map(|_| ()),
+            tracker.lock(date("2024-04-01")),
+        ] {
+            assert!(matches!(result, Err(MetronomeError::Conflict(_))));
+        }
+        // Nothing was changed, and tasks after the lock are not held up
+        assert_eq!(
+            vec!["Task_A", "Task_C"],
+            names(&tracker.list(&ListOptions::default())?)
+        );
+        tracker.end("Task_C")?;
+        assert!(tracker.forced_changes()?.is_empty());
+
+        let mut tracker = tracker.with_force(true);
+        clock.advance(60);
+        tracker.end("Task_A")?;
+        tracker.sync(other.store.as_mut())?;
+        tracker.lock(date("2024-04-01"))?;
+        let forced = tracker.forced_changes()?;
+        let changes: Vec<(Option<i64>, &str)> = forced
+            .iter()
+            .map(|change| (change.task_id, change.command.as_str()))
+            .collect();
+        assert_eq!(
+            vec![(Some(1), "end"), (Some(3), "sync"), (None, "lock")],
+            changes
+        );
+        assert!(forced.iter().all(|change| change.changed_at == NOW + 60));
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_history() -> Result<()> {
+        let (tracker, clock) = setup_with_clock(Config::default())?;
+        let mut tracker = tracker.with_user("alice");
+
+        tracker.start("Task_A", Some("Work"), &[])?;
+        clock.advance(60);
+        tracker.switch("Task_B", None, &[])?;
+        clock.advance(60);
+        tracker.end_all()?;
+        clock.advance(60);
+        tracker.continue_task(Some("Task_A"))?;
+
+        let history = tracker.history(1)?;
+        let commands: Vec<&str> = history.iter().map(|c| c.command.as_str()).collect();
+        assert_eq!(vec!["start", "switch"], commands);
+        assert!(history.iter().all(|change| change.user == "alice"));
+        assert_eq!(None, history[0].old);
+        assert_eq!(history[0].new, history[1].old.clone().unwrap());
+        assert_eq!(Some(60), history[1].new.total_time);
+        assert_eq!(NOW + 60, history[1].changed_at);
+
+        let commands: Vec<String> = tracker
+            .history(2)?
+            .into_iter()
+            .chain(tracker.history(3)?)
+            .map(|change| change.command)
+            .collect();
+        assert_eq!(vec!["switch", "end", "continue"], commands);
+        assert!(matches!(
+            tracker.history(9),
+            Err(MetronomeError::NotFound(_))
+        ));
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_doctor() -> Result<()> {
+        let config = Config {
+            timezone: "UTC".parse().unwrap(),
+            ..Default::default()
+        };
+        let (mut tracker, clock) = setup_with_clock(config)?;
+        let two_days_ago = NOW - 2 * 86400;
+        add_completed_task(&mut tracker, "Task_A", two_days_ago - 86400, 3600, "Work")?;
+        add_completed_task(&mut tracker, "Task_B", two_days_ago, 3600, "Work")?;
+        add_completed_task(&mut tracker, "Task_C", two_days_ago + 1800, 3600, "Work")?;
+        let b = tracker.get(2)?.unwrap();
+        tracker.store.update(&Task {
+            total_time: Some(-5),
+            ..b
+        })?;
+        clock.set(two_days_ago + 7200);
+        tracker.start("Task_D", None, &[])?;
+        clock.set(NOW - 600);
+        tracker.start("Task_D", None, &[])?;
+        clock.set(NOW - 1200);
+        tracker.start("Task_E", None, &[])?;
+        clock.set(NOW);
+
+        let day = TaskTime::from(86400);
+        let problems = tracker.check(day)?;
+        let kinds: Vec<String> = problems.iter().map(Problem::to_string).collect();
+        assert_eq!(
+            vec![
+                "negative_total",
+                "stale",
+                "overlap",
+                "overlap",
+                "overlap",
+                "duplicate_active"
+            ],
+            kinds
+        );
+        let ids: Vec<Vec<i64>> = problems.iter().map(Problem::task_ids).collect();
+        assert_eq!(
+            vec![
+                vec![2],
+                vec![4],
+                vec![2, 3],
+                vec![4, 6],
+                vec![6, 5],
+                vec![4, 5]
+            ],
+            ids
+        );
+        // The end time of a completed task is trusted over the start of the next one
+        let fixable: Vec<bool> = problems
+            .iter()
+            .map(|problem| problem.fixable(NOW, &tracker.config.timezone))
+            .collect();
+        assert_eq!(vec![true, true, false, true, true, true], fixable);
+
+        // Ending the stale task at the end of its day also ends its overlap and duplicate
+        let fixed: Vec<String> = tracker
+            .repair(day)?
+            .iter()
+            .map(Problem::to_string)
+            .collect();
+        assert_eq!(vec!["negative_total", "stale", "overlap"], fixed);
+        let left: Vec<Vec<i64>> = tracker.check(day)?.iter().map(Problem::task_ids).collect();
+        assert_eq!(vec![vec![2, 3]], left);
+        let b = tracker.get(2)?.unwrap();
+        assert_eq!(Some(two_days_ago + 3600), b.end_time);
+        assert_eq!(Some(3600), b.total_time);
+        let d = tracker.get(4)?.unwrap();
+        assert_eq!(Some(midnight("2024-04-21")), d.end_time); // Midnight after it started
+        assert_eq!(TaskStatus::Active, tracker.get(5)?.unwrap().status);
+        assert_eq!(Some(NOW - 600), tracker.get(6)?.unwrap().end_time);
+
+        let commands: Vec<String> = tracker
+            .history(2)?
+            .into_iter()
+            .map(|change| change.command)
+            .collect();
+        assert_eq!(vec!["doctor"], commands);
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_doctor_locked() -> Result<()> {
+        let config = Config {
+            timezone: "UTC".parse().unwrap(),
+            ..Default::default()
+        };
+        let (mut tracker, _) = setup_with_clock(config)?;
+        add_completed_task(&mut tracker, "Task_A", NOW - 3 * 86400, 3600, "Work")?;
+        add_completed_task(&mut tracker, "Task_B", NOW - 3600, 600, "Work")?;
+        for id in [1, 2] {
+            let task = tracker.get(id)?.unwrap();
+            tracker.store.update(&Task {
+                total_time: Some(-5),
+                ..task
+            })?;
+        }
+        tracker.lock(date("2024-04-22"))?;
+
+        // The locked task is skipped and still reported, the other one is fixed
+        let day = TaskTime::from(86400);
+        let fixed = tracker.repair(day)?;
+        assert_eq!(
+            vec![vec![2]],
+            fixed.iter().map(Problem::task_ids).collect::<Vec<_>>()
+        );
+        let problems = tracker.check(day)?;
+        assert_eq!(
+            vec![vec![1]],
+            problems.iter().map(Problem::task_ids).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(-5), tracker.get(1)?.unwrap().total_time);
+
+        // Forcing fixes it, and logs the forced change
+        let mut tracker = tracker.with_force(true);
+        assert_eq!(1, tracker.repair(day)?.len());
+        assert!(tracker.check(day)?.is_empty());
+        let forced = tracker.forced_changes()?;
+        assert_eq!(
+            vec![(Some(1), "doctor")],
+            forced
+                .iter()
+                .map(|change| (change.task_id, change.command.as_str()))
+                .collect::<Vec<_>>()
+        );
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_doctor_unknown_status() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("metronome_{}_doctor.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = SqliteStore::open(&path)?;
+        let clock = FixedClock::new(NOW - 3600);
+        let mut tracker =
+            Tracker::new(Box::new(store), Config::default()).with_clock(clock.clone());
+        tracker.start("Task_A", None, &[])?;
+        tracker.end("Task_A")?;
+
+        // As left by editing the database by hand
+        rusqlite::Connection::open(&path)?
+            .execute("UPDATE tasks SET status = 'Done' WHERE id = 1", [])?;
+        clock.set(NOW);
+        let fixed: Vec<String> = tracker
+            .repair(TaskTime::from(86400))?
+            .iter()
+            .map(Problem::to_string)
+            .collect();
+        assert_eq!(vec!["unknown_status"], fixed);
+
+        // The fix is a change of the status alone, which sync carries to other stores
+        let task = tracker.get(1)?.unwrap();
+        assert_eq!(TaskStatus::Complete, task.status);
+        assert_eq!(NOW, task.modified_at);
+        let fix = tracker.history(1)?.pop().unwrap();
+        assert_eq!("doctor", fix.command);
+        assert_eq!(Some(String::from("Done")), fix.old_status);
+        assert_eq!(Some(NOW - 3600), fix.old.map(|old| old.modified_at));
+        assert_eq!(task, fix.new);
+
+        teardown(tracker);
+        std::fs::remove_file(&path).unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_goals() -> Result<()> {
+        let mut config = Config {
+            timezone: "UTC".parse().unwrap(),
+            ..Default::default()
+        };
+        let (tracker, _) = setup_with_clock(config.clone())?;
+        assert_eq!(
+            Goals {
+                today: None,
+                week: None,
+                budgets: vec![],
+                now: NOW,
+            },
+            tracker.goals()?
+        );
+
+        for (key, value) in [
+            ("daily_target", "8h"),
+            ("target.saturday", "0h"),
+            ("target.sunday", "0h"),
+            ("category.Work.weekly_budget", "20h"),
+        ] {
+            config.set(key, value).unwrap();
+        }
+        let (mut tracker, clock) = setup_with_clock(config)?;
+        let monday = midnight("2024-04-22");
+        add_completed_task(&mut tracker, "Task_A", monday + 9 * 3600, 3 * 3600, "Work")?;
+        add_completed_task(&mut tracker, "Task_B", monday - 12 * 3600, 7200, "Work")?; // Sunday
+
+        // Only the hour after midnight counts toward Monday and its week
+        add_completed_task(&mut tracker, "Task_D", monday - 3600, 7200, "Work")?;
+        clock.set(NOW - 1800);
+        tracker.start("Task_C", None, &[])?;
+        clock.set(NOW);
+
+        let goals = tracker.goals()?;
+        // The running task counts toward the day and week, but not toward Work
+        let goal = |name: &str, tracked: i64, target: i64, expected: i64| Goal {
+            name: String::from(name),
+            tracked: TaskTime::from(tracked),
+            target: TaskTime::from(target),
+            expected: TaskTime::from(expected),
+        };
+        assert_eq!(Some(goal("today", 16200, 28800, 28800)), goals.today);
+        assert_eq!(Some(goal("this week", 16200, 144000, 28800)), goals.week);
+        // One of the five working days is due, so a fifth of the budget
+        assert_eq!(vec![goal("Work", 14400, 72000, 14400)], goals.budgets);
+        assert_eq!(56.25, goals.today.unwrap().percentage());
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gaps_and_overlaps() -> Result<()> {
+        let config = Config {
+            timezone: "UTC".parse().unwrap(),
+            ..Default::default()
+        };
+        let (mut tracker, clock) = setup_with_clock(config)?;
+        // Work hours are 09:00 to 17:00, and NOW is 15:33:20 on Monday
+        let monday = midnight("2024-04-22");
+        let hour = 3600;
+        add_completed_task(
+            &mut tracker,
+            "Task_A",
+            monday + 9 * hour + 1800,
+            5400,
+            "Work",
         )?;
-        add_active_tasks(
-            &mut stmt,
-            "Day",
-            (now - TimeDelta::seconds(3600 * 22)).timestamp(),
-            "Category A",
+        add_completed_task(
+            &mut tracker,
+            "Task_B",
+            monday + 10 * hour + 1800,
+            5400,
+            "Work",
         )?;
-        stmt.finalize()?;
+        add_completed_task(&mut tracker, "Task_C", monday - hour, hour, "Work")?;
+        clock.set(monday + 13 * hour);
+        tracker.start("Task_D", None, &[])?;
+        clock.set(NOW);
+        tracker.end("Task_D")?;
+
+        let gap = |start: i64, end: i64| Gap {
+            start: monday + start,
+            end: monday + end,
+        };
+        let gaps = tracker.gaps(date("2024-04-22"))?;
+        assert_eq!(
+            vec![gap(9 * hour, 9 * hour + 1800), gap(12 * hour, 13 * hour)],
+            gaps
+        );
+        // Nothing after now is a gap yet, and nothing is tracked on Tuesday
+        clock.set(NOW + 3600);
+        let after_d = NOW - monday;
+        assert_eq!(
+            gap(after_d, after_d + hour),
+            tracker.gaps(date("2024-04-22"))?[2]
+        );
+        assert!(tracker.gaps(date("2024-04-23"))?.is_empty());
+        clock.set(NOW);
+
+        let task = tracker.fill(gaps[1], "Lunch", Some("Breaks"), &[])?;
+        assert_eq!(Some(hour), task.total_time);
+        assert_eq!(1, tracker.gaps(date("2024-04-22"))?.len());
+        assert!(matches!(
+            tracker.fill(gaps[1], "Lunch", None, &[]),
+            Err(MetronomeError::Conflict(_))
+        ));
+        assert_eq!("fill", tracker.history(task.id)?[0].command);
+
+        let ids = |overlaps: Vec<(Task, Task)>| -> Vec<(i64, i64)> {
+            overlaps.iter().map(|(a, b)| (a.id, b.id)).collect()
+        };
+        assert_eq!(vec![(1, 2)], ids(tracker.overlaps(None)?));
+        assert_eq!(
+            vec![(1, 2)],
+            ids(tracker.overlaps(Some(date("2024-04-22")))?)
+        );
+        assert!(tracker.overlaps(Some(date("2024-04-21")))?.is_empty());
+
+        teardown(tracker);
+
+        Ok(())
+    }
+
+    fn names(tasks: &[Task]) -> Vec<String> {
+        tasks.iter().map(|task| task.name.clone()).collect()
+    }
+
+    fn filter_test_helper(tracker: &mut Tracker) -> Result<()> {
+        let now = tracker.now();
+        let mut add_active_task = |task_name: &str, start_time: i64, category: &str| {
+            tracker
+                .store
+                .insert(Task {
+                    id: 0,
+                    name: String::from(task_name),
+                    start_time,
+                    end_time: None,
+                    total_time: None,
+                    category: String::from(category),
+                    status: TaskStatus::Active,
+                    tags: vec![],
+                    utc_offset: None,
+                    uuid: String::new(),
+                    modified_at: start_time,
+                    invoice: None,
+                })
+                .map(|_| ())
+        };
+
+        let ago = |timedelta: TimeDelta| now - timedelta.num_seconds();
+
+        add_active_task("First Task", 10, "Misc")?;
+        add_active_task("Over a Year", ago(TimeDelta::days(400)), "Category A")?;
+        add_active_task("Within a Year", ago(TimeDelta::days(300)), "Category B")?;
+        add_active_task("SemiAnnual", ago(TimeDelta::weeks(23)), "Misc")?;
+        add_active_task("Quarter", ago(TimeDelta::weeks(10)), "Category A")?;
+        add_active_task("Month", ago(TimeDelta::days(25)), "Category B")?;
+        add_active_task("Week", ago(TimeDelta::days(5)), "Misc")?;
+        add_active_task("Day", ago(TimeDelta::seconds(3600 * 22)), "Category A")?;
 
         Ok(())
     }
+
     #[test]
-    fn test_filtered_list() -> rusqlite::Result<()> {
-        let conn = setup()?;
+    fn test_filtered_list() -> Result<()> {
+        let mut tracker = setup()?;
 
-        filter_test_helper(&conn)?;
+        filter_test_helper(&mut tracker)?;
 
         for filter in Filter::iter() {
-            let List(num_returned) = list_all(&conn, filter.clone())? else {
-                unreachable!()
-            };
+            let num_returned = tracker
+                .list(&ListOptions::new(ListMode::All, filter))?
+                .len();
 
             let expected_num: usize = match filter {
                 Filter::Day => 1,
@@ -802,48 +2662,51 @@ mod tests {
             assert_eq!(expected_num, num_returned);
         }
 
-        teardown(conn);
+        // Every filter can be given by name, so "all" overrides a configured list_filter
+        for filter in Filter::iter() {
+            assert_eq!(filter, Filter::from(Some(&String::from(filter.name()))));
+        }
+        assert_eq!(Filter::All, Filter::from(Some(&String::from("a"))));
+
+        teardown(tracker);
 
         Ok(())
     }
 
     #[test]
-    fn test_filtered_total() -> rusqlite::Result<()> {
-        let conn = setup()?;
+    fn test_filtered_total() -> Result<()> {
+        let mut tracker = setup()?;
 
         // Create tasks and make sure they are completed
-        filter_test_helper(&conn)?;
-        end_all_active(&conn)?;
+        filter_test_helper(&mut tracker)?;
+        tracker.end_all()?;
 
-        // Apply Month filter to sum_task_times
-        let SumTaskTimes(total_time) = sum_task_times(&conn, Filter::Month, None)? else {
-            panic!("SumTaskTimes enum was not returned.")
-        };
+        // Apply Month filter to totals
+        let total_time = tracker.totals(Filter::Month, None)?.time.total_seconds();
 
         // Sum of tasks "Day", "Week", "Month" from filter_test_helper
         let expected_time =
             (TimeDelta::seconds(3600 * 22) + TimeDelta::days(5) + TimeDelta::days(25))
                 .num_seconds();
-        assert!((expected_time - total_time).abs() < 5);
+        assert_eq!(expected_time, total_time);
 
-        teardown(conn);
+        teardown(tracker);
 
         Ok(())
     }
 
     #[test]
-    fn test_category_total() -> rusqlite::Result<()> {
-        let conn = setup()?;
+    fn test_category_total() -> Result<()> {
+        let mut tracker = setup()?;
 
         // Create tasks and make sure they are completed
-        filter_test_helper(&conn)?;
-        end_all_active(&conn)?;
+        filter_test_helper(&mut tracker)?;
+        tracker.end_all()?;
 
-        let SumTaskTimes(total_time) =
-            sum_task_times(&conn, Filter::All, Some(&String::from("Category B")))?
-        else {
-            panic!("SumTaskTimes enum was not returned.")
-        };
+        let total_time = tracker
+            .totals(Filter::All, Some("Category B"))?
+            .time
+            .total_seconds();
 
         // Sum of tasks "Within a Year", "Month" from filter_test_helper (Category B only)
         let expected_time = (TimeDelta::days(300) + TimeDelta::days(25)).num_seconds();
@@ -852,9 +2715,9 @@ mod tests {
             "Expected time: {}, Returned time: {}",
             expected_time, total_time
         );
-        assert!((expected_time - total_time).abs() <= 2);
+        assert_eq!(expected_time, total_time);
 
-        teardown(conn);
+        teardown(tracker);
 
         Ok(())
     }
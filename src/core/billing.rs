@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use strum_macros::{Display, EnumString};
+
+use super::task::Task;
+use super::tasktime::TaskTime;
+use super::timezone::DisplayZone;
+
+/// The hourly rate and billability of a task, see `Config::billing_for`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Billing {
+    pub rate: Option<f64>,
+    pub currency: String, // Empty when none is set
+    pub billable: bool,
+}
+
+/// A sum of money in one currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Amount {
+    pub value: f64,
+    pub currency: String,
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.currency.as_str() {
+            "" => write!(f, "{:.2}", self.value),
+            currency => write!(f, "{:.2} {}", self.value, currency),
+        }
+    }
+}
+
+/// Billable time and what it is worth, with one amount per currency.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Billed {
+    pub time: TaskTime, // Rounded time of the billable tasks
+    pub amounts: Vec<Amount>,
+}
+
+impl Billed {
+    /// Bills completed tasks, rounding the tasks with the same rate together. Tasks without a
+    /// rate count toward the billable time but not the amounts.
+    pub fn bill(tasks: Vec<(Task, Billing)>, rounding: &Rounding, zone: &DisplayZone) -> Billed {
+        let mut groups: Vec<(Billing, Vec<Task>)> = vec![];
+        for (task, billing) in tasks {
+            match groups.iter_mut().find(|(group, _)| *group == billing) {
+                Some((_, tasks)) => tasks.push(task),
+                None => groups.push((billing, vec![task])),
+            }
+        }
+
+        let mut billed = Billed::default();
+        for (billing, tasks) in groups.into_iter().filter(|(billing, _)| billing.billable) {
+            let time = rounding.apply(&tasks, zone);
+            billed.time += time;
+            if let Some(rate) = billing.rate {
+                billed.add(Amount {
+                    value: time.hours() * rate,
+                    currency: billing.currency,
+                });
+            }
+        }
+        billed
+    }
+
+    pub fn add(&mut self, amount: Amount) {
+        match self
+            .amounts
+            .iter_mut()
+            .find(|existing| existing.currency == amount.currency)
+        {
+            Some(existing) => existing.value += amount.value,
+            None => {
+                self.amounts.push(amount);
+                self.amounts.sort_by(|a, b| a.currency.cmp(&b.currency));
+            }
+        }
+    }
+}
+
+impl std::ops::AddAssign<&Billed> for Billed {
+    fn add_assign(&mut self, other: &Billed) {
+        self.time += other.time;
+        for amount in &other.amounts {
+            self.add(amount.clone());
+        }
+    }
+}
+
+/// Which way a duration is rounded to a whole number of increments.
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum RoundingMode {
+    /// Halfway rounds up.
+    Nearest,
+    Up,
+    Down,
+}
+
+/// What a rounding rule is applied to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum RoundingScope {
+    /// Every task on its own.
+    #[default]
+    Task,
+    /// The time of all tasks started on the same day.
+    Day,
+}
+
+/// How billed time is rounded, written e.g. "up 15m" or "nearest 6m per day".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rounding {
+    pub mode: RoundingMode,
+    pub increment: TaskTime,
+    pub scope: RoundingScope,
+}
+
+impl Rounding {
+    /// Leaves durations as they are, written "none". Turns off a rounding rule that would
+    /// otherwise apply.
+    pub const NONE: Rounding = Rounding {
+        mode: RoundingMode::Nearest,
+        increment: TaskTime::ZERO,
+        scope: RoundingScope::Task,
+    };
+
+    pub fn round(&self, time: TaskTime) -> TaskTime {
+        let increment = self.increment.total_seconds();
+        if increment <= 0 {
+            return time;
+        }
+        let seconds = time.total_seconds();
+        let down = seconds.div_euclid(increment) * increment;
+        let remainder = seconds - down;
+        let rounded = match self.mode {
+            RoundingMode::Down => down,
+            RoundingMode::Up if remainder > 0 => down + increment,
+            RoundingMode::Up => down,
+            RoundingMode::Nearest if remainder * 2 >= increment => down + increment,
+            RoundingMode::Nearest => down,
+        };
+        TaskTime::from(rounded)
+    }
+
+    /// Rounded total time of completed tasks. Days start at midnight in `zone`.
+    pub fn apply(&self, tasks: &[Task], zone: &DisplayZone) -> TaskTime {
+        let time = |task: &Task| TaskTime::from(task.total_time.unwrap_or(0));
+        match self.scope {
+            RoundingScope::Task => tasks.iter().map(|task| self.round(time(task))).sum(),
+            RoundingScope::Day => {
+                let mut days: BTreeMap<i64, TaskTime> = BTreeMap::new();
+                for task in tasks {
+                    *days.entry(zone.start_of_day(task.start_time)).or_default() += time(task);
+                }
+                days.into_values().map(|day| self.round(day)).sum()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Rounding::NONE {
+            return write!(f, "none");
+        }
+        write!(
+            f,
+            "{} {} per {}",
+            self.mode,
+            self.increment.short(),
+            self.scope
+        )
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    /// Accepts a mode, an increment and optionally "per task" or "per day", or "none".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid rounding \"{}\", expected e.g. \"up 15m\", \"nearest 6m per day\" or \"none\"",
+                s
+            )
+        };
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (mode, increment, scope) = match words[..] {
+            ["none"] => return Ok(Rounding::NONE),
+            [mode, increment] => (mode, increment, "task"),
+            [mode, increment, "per", scope] => (mode, increment, scope),
+            _ => return Err(invalid()),
+        };
+        let increment: TaskTime = increment.parse().map_err(|_| invalid())?;
+        if increment.total_seconds() <= 0 {
+            return Err(invalid());
+        }
+        Ok(Rounding {
+            mode: mode.parse().map_err(|_| invalid())?,
+            increment,
+            scope: scope.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding() {
+        let round = |rule: &str, seconds: i64| {
+            let rounding: Rounding = rule.parse().unwrap();
+            rounding.round(TaskTime::from(seconds)).total_seconds()
+        };
+        assert_eq!(900, round("up 15m", 1));
+        assert_eq!(900, round("up 15m", 900));
+        assert_eq!(0, round("down 15m", 899));
+        assert_eq!(900, round("nearest 15m", 450));
+        assert_eq!(0, round("nearest 15m", 449));
+        assert_eq!(0, round("up 6m", 0));
+        assert_eq!(449, round("none", 449));
+
+        for rule in [
+            "up 15m per task",
+            "nearest 6m per day",
+            "down 1h0m per task",
+            "none",
+        ] {
+            assert_eq!(rule, rule.parse::<Rounding>().unwrap().to_string());
+        }
+        for rule in [
+            "",
+            "up",
+            "up 0m",
+            "sideways 15m",
+            "up 15m per week",
+            "up 15m each day",
+        ] {
+            assert!(rule.parse::<Rounding>().is_err(), "{}", rule);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use chrono::Local;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Source of the current time for the Tracker.
+pub trait Clock {
+    /// Current unix timestamp in seconds.
+    fn now(&self) -> i64;
+}
+
+/// Reads the time from the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Local::now().timestamp()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a test can keep a
+/// clone to advance the clock it gave to a Tracker.
+#[derive(Debug, Default, Clone)]
+pub struct FixedClock {
+    now: Rc<Cell<i64>>,
+}
+
+impl FixedClock {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: Rc::new(Cell::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        self.now.set(self.now.get() + seconds);
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.now.get()
+    }
+}
+
+impl Clock for Box<dyn Clock> {
+    fn now(&self) -> i64 {
+        self.as_ref().now()
+    }
+}
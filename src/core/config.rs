@@ -0,0 +1,649 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use chrono::Weekday;
+use strum_macros::{Display, EnumString};
+use toml::{Table, Value};
+
+use super::billing::{Billing, Rounding};
+use super::error::{MetronomeError, Result};
+use super::filters::Filter;
+use super::gaps::WorkHours;
+use super::task::Task;
+use super::tasktime::{DurationStyle, TaskTime};
+use super::timezone::DisplayZone;
+use super::ListMode;
+
+pub const CONFIG_VAR: &str = "METRONOME_CONFIG";
+pub const SINGLE_ACTIVE_VAR: &str = "METRONOME_SINGLE_ACTIVE";
+pub const TIMEZONE_VAR: &str = "METRONOME_TZ";
+
+/// Settings that can be given in the config file, in the order `config list` shows them.
+pub const KEYS: [&str; 16] = [
+    "default_category",
+    "date_format",
+    "duration_style",
+    "week_start",
+    "list_mode",
+    "list_filter",
+    "timezone",
+    "output_format",
+    "single_active",
+    "rounding",
+    "rate",
+    "currency",
+    "billable",
+    "tax",
+    "work_hours",
+    "daily_target",
+];
+
+/// Settings of a single category, given as `category.<name>.<setting>`, i.e. in a
+/// `[category.<name>]` table of the config file.
+pub const CATEGORY_KEYS: [&str; 6] = [
+    "rounding",
+    "rate",
+    "currency",
+    "billable",
+    "tax",
+    "weekly_budget",
+];
+
+/// Targets of single weekdays are given as `target.<weekday>`, e.g. `target.friday`, in a
+/// `[target]` table of the config file. They take precedence over `daily_target`.
+const TARGET_PREFIX: &str = "target.";
+
+/// Settings of a single tag, given as `tag.<name>.<setting>`. They take precedence over
+/// the settings of the task's category.
+pub const TAG_KEYS: [&str; 3] = ["rate", "currency", "billable"];
+
+/// How command results are printed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Csv,
+    Json,
+}
+
+/// User preferences that change how tasks are tracked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Only allow one active task; starting a task ends the running one first.
+    pub single_active: bool,
+    /// Zone used to display timestamps and decide where a day starts.
+    pub timezone: DisplayZone,
+    /// Category of tasks started without one.
+    pub default_category: String,
+    /// strftime format of displayed timestamps.
+    pub date_format: String,
+    pub duration_style: DurationStyle,
+    /// First day of the week, used for the weekly time in `status`.
+    pub week_start: Weekday,
+    /// Tasks listed when `list` is given no mode or filter.
+    pub list_mode: ListMode,
+    pub list_filter: Filter,
+    pub output_format: OutputFormat,
+    /// Rounding of billed time in categories without a rule of their own.
+    pub rounding: Rounding,
+    /// Hourly rate of tasks whose tags and category have none.
+    pub rate: Option<f64>,
+    pub currency: String,
+    pub billable: bool,
+    /// Tax added to invoices, in percent.
+    pub tax: f64,
+    /// The part of each day `gaps` expects to be tracked.
+    pub work_hours: WorkHours,
+    /// Time to track each day, for `goals`.
+    pub daily_target: Option<TaskTime>,
+    /// Targets of single weekdays, Monday first, overriding `daily_target`.
+    pub weekday_targets: [Option<TaskTime>; 7],
+    pub categories: BTreeMap<String, GroupSettings>,
+    pub tags: BTreeMap<String, GroupSettings>,
+}
+
+/// Settings that only apply to the tasks of one category or with one tag. Those left unset
+/// fall back to the settings above them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GroupSettings {
+    /// Overrides `Config::rounding`. Only categories are rounded.
+    pub rounding: Option<Rounding>,
+    pub rate: Option<f64>,
+    pub currency: Option<String>,
+    pub billable: Option<bool>,
+    /// Overrides `Config::tax` on the invoices of a category, i.e. a client.
+    pub tax: Option<f64>,
+    /// Time to spend on a category each week, for `goals`.
+    pub weekly_budget: Option<TaskTime>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            single_active: false,
+            timezone: DisplayZone::default(),
+            default_category: String::from("Misc"),
+            date_format: String::from("%c"),
+            duration_style: DurationStyle::default(),
+            week_start: Weekday::Mon,
+            list_mode: ListMode::default(),
+            list_filter: Filter::default(),
+            output_format: OutputFormat::default(),
+            rounding: Rounding::NONE,
+            rate: None,
+            currency: String::new(),
+            billable: true,
+            tax: 0.0,
+            work_hours: WorkHours::default(),
+            daily_target: None,
+            weekday_targets: [None; 7],
+            categories: BTreeMap::new(),
+            tags: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, then lets the environment variables override it. Invalid
+    /// settings are reported and left at their defaults.
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        if let Some(path) = path() {
+            match read(&path) {
+                Ok(settings) => {
+                    for (key, value) in settings {
+                        if let Err(e) = config.set(&key, &value) {
+                            eprintln!("Ignoring {} in {}: {}", key, path.display(), e);
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Ignoring {}: {}", path.display(), e),
+            }
+        }
+
+        if let Ok(zone) = env::var(TIMEZONE_VAR) {
+            match zone.parse() {
+                Ok(zone) => config.timezone = zone,
+                Err(e) => eprintln!("Ignoring {}: {}", TIMEZONE_VAR, e),
+            }
+        }
+        if let Ok(value) = env::var(SINGLE_ACTIVE_VAR) {
+            match parse_bool(&value) {
+                Some(single_active) => config.single_active = single_active,
+                None => eprintln!("Ignoring {}: expected {}", SINGLE_ACTIVE_VAR, BOOL),
+            }
+        }
+
+        config
+    }
+
+    /// The rounding rule for billed time in `category`.
+    pub fn rounding_for(&self, category: &str) -> Rounding {
+        self.categories
+            .get(category)
+            .and_then(|settings| settings.rounding)
+            .unwrap_or(self.rounding)
+    }
+
+    /// Whether billed time is rounded in any category.
+    pub fn has_rounding(&self) -> bool {
+        self.rounding != Rounding::NONE
+            || self
+                .categories
+                .values()
+                .any(|settings| settings.rounding.is_some_and(|r| r != Rounding::NONE))
+    }
+
+    /// The rate, currency and billability of `task`. Its tags come first, in order, then
+    /// its category, then the defaults.
+    pub fn billing_for(&self, task: &Task) -> Billing {
+        let groups: Vec<&GroupSettings> = task
+            .tags
+            .iter()
+            .filter_map(|tag| self.tags.get(tag))
+            .chain(self.categories.get(&task.category))
+            .collect();
+        Billing {
+            rate: groups.iter().find_map(|group| group.rate).or(self.rate),
+            currency: groups
+                .iter()
+                .find_map(|group| group.currency.clone())
+                .unwrap_or_else(|| self.currency.clone()),
+            billable: groups
+                .iter()
+                .find_map(|group| group.billable)
+                .unwrap_or(self.billable),
+        }
+    }
+
+    /// The tax rate in percent on invoices for `category`.
+    pub fn tax_for(&self, category: &str) -> f64 {
+        self.categories
+            .get(category)
+            .and_then(|settings| settings.tax)
+            .unwrap_or(self.tax)
+    }
+
+    /// The time to track on `weekday`, if any.
+    pub fn target_for(&self, weekday: Weekday) -> Option<TaskTime> {
+        self.weekday_targets[weekday.num_days_from_monday() as usize].or(self.daily_target)
+    }
+
+    /// Every setting, including those of the weekdays, categories and tags that have any.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = KEYS.iter().map(|key| key.to_string()).collect();
+        for (day, target) in self.weekday_targets.iter().enumerate() {
+            if target.is_some() {
+                let weekday = Weekday::try_from(day as u8).unwrap_or(Weekday::Mon);
+                keys.push(format!("{}{}", TARGET_PREFIX, weekday_name(weekday)));
+            }
+        }
+        let groups = [
+            ("category", &self.categories, &CATEGORY_KEYS[..]),
+            ("tag", &self.tags, &TAG_KEYS[..]),
+        ];
+        for (kind, settings, fields) in groups {
+            for name in settings.keys() {
+                for field in fields {
+                    let key = format!("{}.{}.{}", kind, name, field);
+                    if self.get(&key).is_some() {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+        keys
+    }
+
+    /// Changes one setting, given as it is written in the config file.
+    pub fn set(&mut self, key: &str, value: &str) -> std::result::Result<(), String> {
+        if let Some((kind, name, field)) = group_key(key) {
+            return self.set_group(kind, name, field, value);
+        }
+        if let Some(weekday) = target_key(key) {
+            let target = parse_target(value)
+                .map_err(|_| format!("invalid {} \"{}\", expected {}", key, value, TARGET))?;
+            self.weekday_targets[weekday.num_days_from_monday() as usize] = target;
+            return Ok(());
+        }
+
+        let invalid =
+            |expected: &str| format!("invalid {} \"{}\", expected {}", key, value, expected);
+        match key {
+            "default_category" if value.is_empty() => return Err(invalid("a category name")),
+            "default_category" => self.default_category = value.to_string(),
+            "date_format" => {
+                // chrono only reports a bad format when it is used, so try it once here
+                if chrono::format::StrftimeItems::new(value)
+                    .any(|item| item == chrono::format::Item::Error)
+                {
+                    return Err(invalid("a strftime format such as %Y-%m-%d %H:%M"));
+                }
+                self.date_format = value.to_string();
+            }
+            "duration_style" => {
+                self.duration_style = value
+                    .parse()
+                    .map_err(|_| invalid("full, compact, clock, decimal or days"))?
+            }
+            "week_start" => {
+                self.week_start = value.parse().map_err(|_| invalid("a day such as monday"))?
+            }
+            "list_mode" => {
+                self.list_mode = value
+                    .parse()
+                    .map_err(|_| invalid("all, active or complete"))?
+            }
+            "list_filter" => {
+                self.list_filter = value
+                    .parse()
+                    .map_err(|_| invalid("all, day, week, month, quarter, semi or year"))?
+            }
+            "timezone" => self.timezone = value.parse()?,
+            "output_format" => {
+                self.output_format = value.parse().map_err(|_| invalid("table, csv or json"))?
+            }
+            "single_active" => {
+                self.single_active = parse_bool(value).ok_or_else(|| invalid(BOOL))?
+            }
+            "rounding" => self.rounding = value.parse()?,
+            "rate" => self.rate = parse_rate(value).map_err(|_| invalid(RATE))?,
+            "currency" if value.is_empty() => return Err(invalid("a currency such as EUR")),
+            "currency" => self.currency = value.to_string(),
+            "billable" => self.billable = parse_bool(value).ok_or_else(|| invalid(BOOL))?,
+            "tax" => self.tax = parse_tax(value).ok_or_else(|| invalid(TAX))?,
+            "work_hours" => self.work_hours = value.parse()?,
+            "daily_target" => {
+                self.daily_target = parse_target(value).map_err(|_| invalid(TARGET))?
+            }
+            _ => return Err(format!("unknown setting \"{}\"", key)),
+        }
+        Ok(())
+    }
+
+    fn set_group(
+        &mut self,
+        kind: &str,
+        name: &str,
+        field: &str,
+        value: &str,
+    ) -> std::result::Result<(), String> {
+        let invalid =
+            |expected: &str| format!("invalid {} \"{}\", expected {}", field, value, expected);
+        // Changed on a copy, so an invalid value leaves no empty group behind
+        let mut settings = self.group(kind, name).cloned().unwrap_or_default();
+        match field {
+            "rounding" if kind == "category" => settings.rounding = Some(value.parse()?),
+            "rate" => settings.rate = parse_rate(value).map_err(|_| invalid(RATE))?,
+            "currency" if value.is_empty() => return Err(invalid("a currency such as EUR")),
+            "currency" => settings.currency = Some(value.to_string()),
+            "billable" => settings.billable = Some(parse_bool(value).ok_or_else(|| invalid(BOOL))?),
+            "tax" if kind == "category" => {
+                settings.tax = Some(parse_tax(value).ok_or_else(|| invalid(TAX))?)
+            }
+            "weekly_budget" if kind == "category" => {
+                settings.weekly_budget = parse_target(value).map_err(|_| invalid(TARGET))?
+            }
+            _ => return Err(format!("unknown {} setting \"{}\"", kind, field)),
+        }
+        let groups = match kind {
+            "category" => &mut self.categories,
+            _ => &mut self.tags,
+        };
+        groups.insert(name.to_string(), settings);
+        Ok(())
+    }
+
+    fn group(&self, kind: &str, name: &str) -> Option<&GroupSettings> {
+        match kind {
+            "category" => self.categories.get(name),
+            _ => self.tags.get(name),
+        }
+    }
+
+    /// One setting, written as `set` accepts it.
+    pub fn get(&self, key: &str) -> Option<String> {
+        if let Some((kind, name, field)) = group_key(key) {
+            let settings = self.group(kind, name)?;
+            return match field {
+                "rounding" => settings.rounding.map(|rounding| rounding.to_string()),
+                "rate" => settings.rate.map(|rate| rate.to_string()),
+                "currency" => settings.currency.clone(),
+                "billable" => settings.billable.map(|billable| billable.to_string()),
+                "tax" => settings.tax.map(|tax| tax.to_string()),
+                "weekly_budget" => settings.weekly_budget.map(|budget| budget.short()),
+                _ => None,
+            };
+        }
+        if let Some(weekday) = target_key(key) {
+            return self.weekday_targets[weekday.num_days_from_monday() as usize]
+                .map(|target| target.short());
+        }
+        let value = match key {
+            "default_category" => self.default_category.clone(),
+            "date_format" => self.date_format.clone(),
+            "duration_style" => self.duration_style.to_string(),
+            "week_start" => weekday_name(self.week_start).to_string(),
+            "list_mode" => self.list_mode.to_string(),
+            "list_filter" => self.list_filter.name().to_string(),
+            "timezone" => self.timezone.to_string(),
+            "output_format" => self.output_format.to_string(),
+            "single_active" => self.single_active.to_string(),
+            "rounding" => self.rounding.to_string(),
+            "rate" => self
+                .rate
+                .map_or_else(|| String::from("none"), |rate| rate.to_string()),
+            "currency" => self.currency.clone(),
+            "billable" => self.billable.to_string(),
+            "tax" => self.tax.to_string(),
+            "work_hours" => self.work_hours.to_string(),
+            "daily_target" => self
+                .daily_target
+                .map_or_else(|| String::from("none"), |target| target.short()),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Whether `key` names a setting, including the settings of any category or tag.
+pub fn is_key(key: &str) -> bool {
+    KEYS.contains(&key)
+        || target_key(key).is_some()
+        || group_key(key).is_some_and(|(kind, _, field)| match kind {
+            "category" => CATEGORY_KEYS.contains(&field),
+            _ => TAG_KEYS.contains(&field),
+        })
+}
+
+// "category" or "tag", the name and the setting of a "category.<name>.<setting>" or
+// "tag.<name>.<setting>" key. Names may contain dots themselves.
+fn group_key(key: &str) -> Option<(&str, &str, &str)> {
+    let (kind, rest) = key.split_once('.')?;
+    if kind != "category" && kind != "tag" {
+        return None;
+    }
+    let (name, field) = rest.rsplit_once('.').filter(|(name, _)| !name.is_empty())?;
+    Some((kind, name, field))
+}
+
+// The weekday of a "target.<weekday>" key
+fn target_key(key: &str) -> Option<Weekday> {
+    key.strip_prefix(TARGET_PREFIX)?.parse().ok()
+}
+
+const TARGET: &str = "a duration such as 8h or 7h30m, or none";
+
+fn parse_target(value: &str) -> std::result::Result<Option<TaskTime>, ()> {
+    match value {
+        "none" => Ok(None),
+        value => value.parse().map(Some).map_err(|_| ()),
+    }
+}
+
+const RATE: &str = "an hourly rate such as 85 or 92.50, or none";
+
+fn parse_rate(value: &str) -> std::result::Result<Option<f64>, ()> {
+    if value == "none" {
+        return Ok(None);
+    }
+    match value.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate >= 0.0 => Ok(Some(rate)),
+        _ => Err(()),
+    }
+}
+
+const TAX: &str = "a tax rate in percent such as 19 or 7.5";
+
+fn parse_tax(value: &str) -> Option<f64> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|tax| (0.0..=100.0).contains(tax))
+}
+
+/// The config file: `$METRONOME_CONFIG`, or metronome/config.toml in the user's config
+/// directory, e.g. ~/.config on Linux.
+pub fn path() -> Option<PathBuf> {
+    match env::var_os(CONFIG_VAR) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => dirs::config_dir().map(|dir| dir.join("metronome").join("config.toml")),
+    }
+}
+
+/// The settings in a config file, in the order they are written. Settings in tables are
+/// named by their path, e.g. `category.Work.rounding`. A missing file has none.
+pub fn read(path: &Path) -> Result<Vec<(String, String)>> {
+    let mut settings = vec![];
+    flatten("", read_table(path)?, &mut settings);
+    Ok(settings)
+}
+
+fn flatten(prefix: &str, table: Table, settings: &mut Vec<(String, String)>) {
+    for (key, value) in table {
+        let key = format!("{}{}", prefix, key);
+        match value {
+            Value::Table(table) => flatten(&format!("{}.", key), table, settings),
+            Value::String(value) => settings.push((key, value)),
+            value => settings.push((key, value.to_string())),
+        }
+    }
+}
+
+/// Changes one setting in a config file, creating the file if needed. Other settings and
+/// their order are kept.
+pub fn write(path: &Path, key: &str, value: &str) -> Result<()> {
+    Config::default()
+        .set(key, value)
+        .map_err(MetronomeError::Validation)?;
+
+    let mut table = read_table(path)?;
+    // Booleans, rates and tax are written as TOML booleans and numbers
+    let field = key.rsplit('.').next().unwrap_or(key);
+    let value = match (field, value.parse::<f64>()) {
+        ("single_active" | "billable", _) => Value::Boolean(parse_bool(value) == Some(true)),
+        ("rate" | "tax", Ok(number)) => Value::Float(number),
+        _ => Value::String(value.to_string()),
+    };
+    // Settings of groups and weekdays go in tables of their own
+    let tables = match (group_key(key), target_key(key)) {
+        (Some((kind, name, field)), _) => Some((vec![kind, name], field)),
+        // Written by their full name, so "sat" and "Saturday" end up as the same setting
+        (None, Some(weekday)) => Some((vec!["target"], weekday_name(weekday))),
+        (None, None) => None,
+    };
+    match tables {
+        Some((tables, field)) => {
+            let mut parent = &mut table;
+            for name in tables {
+                let child = parent
+                    .entry(name)
+                    .or_insert_with(|| Value::Table(Table::new()));
+                let Value::Table(child) = child else {
+                    return Err(error(path, format!("{} is not a table", name)));
+                };
+                parent = child;
+            }
+            parent.insert(field.to_string(), value);
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| error(path, e))?;
+    }
+    fs::write(path, table.to_string()).map_err(|e| error(path, e))
+}
+
+fn read_table(path: &Path) -> Result<Table> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .parse()
+            .map_err(|e: toml::de::Error| error(path, e.message())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Table::new()),
+        Err(e) => Err(error(path, e)),
+    }
+}
+
+fn error(path: &Path, e: impl std::fmt::Display) -> MetronomeError {
+    MetronomeError::Storage(format!("{}: {}", path.display(), e))
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "monday",
+        Weekday::Tue => "tuesday",
+        Weekday::Wed => "wednesday",
+        Weekday::Thu => "thursday",
+        Weekday::Fri => "friday",
+        Weekday::Sat => "saturday",
+        Weekday::Sun => "sunday",
+    }
+}
+
+const BOOL: &str = "true or false, or yes/no, on/off or 1/0";
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() -> Result<()> {
+        let mut config = Config::default();
+        for (key, value) in [
+            ("date_format", "%Y-%m-%d %H:%M"),
+            ("duration_style", "compact"),
+            ("week_start", "sunday"),
+            ("list_mode", "active"),
+            ("list_filter", "week"),
+            ("timezone", "Europe/Berlin"),
+            ("output_format", "json"),
+            ("single_active", "true"),
+            ("work_hours", "08:30-16:30"),
+            ("daily_target", "8h0m"),
+            ("target.friday", "4h30m"),
+            ("category.Work.weekly_budget", "20h0m"),
+        ] {
+            config.set(key, value).unwrap();
+            assert_eq!(Some(String::from(value)), config.get(key));
+        }
+        assert_eq!(Weekday::Sun, config.week_start);
+        assert_eq!(Filter::Week, config.list_filter);
+
+        // Invalid values leave the setting as it was
+        for (key, value) in [
+            ("date_format", "%Q"),
+            ("week_start", "someday"),
+            ("list_filter", "decade"),
+            ("single_active", "maybe"),
+            ("work_hours", "17:00-09:00"),
+            ("daily_target", "soon"),
+            ("target.someday", "1h"),
+            ("colour", "red"),
+        ] {
+            assert!(config.set(key, value).is_err(), "{} = {}", key, value);
+        }
+        assert_eq!("%Y-%m-%d %H:%M", config.date_format);
+        assert_eq!(None, config.get("colour"));
+
+        // Booleans are read as for METRONOME_SINGLE_ACTIVE
+        for (value, expected) in [("no", false), ("1", true), ("off", false), ("YES", true)] {
+            config.set("single_active", value).unwrap();
+            assert_eq!(expected, config.single_active, "{}", value);
+        }
+
+        // Writing keeps the other settings, in the order they were written
+        let path =
+            std::env::temp_dir().join(format!("metronome_{}_config.toml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(read(&path)?.is_empty());
+        write(&path, "week_start", "sunday")?;
+        write(&path, "single_active", "yes")?;
+        write(&path, "week_start", "friday")?;
+        assert!(write(&path, "week_start", "someday").is_err());
+        assert_eq!(
+            vec![
+                (String::from("week_start"), String::from("friday")),
+                (String::from("single_active"), String::from("true")),
+            ],
+            read(&path)?
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use chrono::Days;
+use strum_macros::Display;
+
+use super::store::UnknownStatus;
+use super::task::{Task, TaskStatus};
+use super::tasktime::TaskTime;
+use super::timezone::DisplayZone;
+
+/// Something wrong with the stored tasks, e.g. after years of editing the store by hand.
+#[derive(Debug, Clone, PartialEq, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Problem {
+    /// A status other than Active or Complete.
+    UnknownStatus(UnknownStatus),
+    /// A completed task without a total time.
+    MissingTotal(Task),
+    NegativeTotal(Task),
+    /// A total time other than the time between the start and end of the task.
+    WrongTotal(Task),
+    /// A task that has been active for longer than expected, likely because it was never
+    /// ended.
+    Stale(Task),
+    /// Two tasks that ran at the same time, the one that started first first.
+    Overlap(Task, Task),
+    /// Active tasks with the same name, which `end` would end together. Oldest first.
+    DuplicateActive(Vec<Task>),
+}
+
+impl Problem {
+    /// The tasks with the problem.
+    pub fn task_ids(&self) -> Vec<i64> {
+        match self {
+            Problem::UnknownStatus(unknown) => vec![unknown.id],
+            Problem::MissingTotal(task)
+            | Problem::NegativeTotal(task)
+            | Problem::WrongTotal(task)
+            | Problem::Stale(task) => vec![task.id],
+            Problem::Overlap(first, second) => vec![first.id, second.id],
+            Problem::DuplicateActive(tasks) => tasks.iter().map(|task| task.id).collect(),
+        }
+    }
+
+    /// Whether `Tracker::repair` can fix the problem, rather than it needing a fix by hand.
+    pub fn fixable(&self, now: i64, zone: &DisplayZone) -> bool {
+        matches!(self, Problem::UnknownStatus(_)) || !self.repaired(now, zone).is_empty()
+    }
+
+    /// The tasks as the fix of the problem leaves them, changed at `now`. An unknown status
+    /// cannot be read as a task, so it is fixed in the store.
+    pub(crate) fn repaired(&self, now: i64, zone: &DisplayZone) -> Vec<Task> {
+        let repaired = match self {
+            Problem::UnknownStatus(_) => vec![],
+            // The end time is trusted over the total time
+            Problem::MissingTotal(task)
+            | Problem::NegativeTotal(task)
+            | Problem::WrongTotal(task) => match task.end_time {
+                Some(end_time) if end_time >= task.start_time => vec![Task {
+                    total_time: Some(end_time - task.start_time),
+                    ..task.clone()
+                }],
+                _ => vec![],
+            },
+            // Ended at the end of the day it started, as it was most likely left running
+            Problem::Stale(task) => {
+                let end_of_day = zone
+                    .date(task.start_time)
+                    .and_then(|date| date.checked_add_days(Days::new(1)))
+                    .map(|date| zone.midnight(date))
+                    .unwrap_or(now);
+                vec![ended(task, end_of_day.min(now))]
+            }
+            // The first task ends when the second started, as `switch` would have done. A
+            // recorded end time is trusted, so overlapping completed tasks are fixed by hand.
+            Problem::Overlap(first, second)
+                if first.start_time < second.start_time
+                    && (first.status == TaskStatus::Active || first.end_time.is_none()) =>
+            {
+                vec![ended(first, second.start_time)]
+            }
+            Problem::Overlap(..) => vec![],
+            Problem::DuplicateActive(tasks) => match tasks.last() {
+                Some(latest) => tasks
+                    .iter()
+                    .filter(|task| task.start_time < latest.start_time)
+                    .map(|task| ended(task, latest.start_time))
+                    .collect(),
+                None => vec![],
+            },
+        };
+        repaired
+            .into_iter()
+            .map(|task| Task {
+                modified_at: now,
+                ..task
+            })
+            .collect()
+    }
+}
+
+/// The problems with `tasks`. Tasks active for longer than `stale_after` are stale, and active
+/// tasks count as running until `now` when looking for overlaps.
+pub(crate) fn diagnose(tasks: &[Task], now: i64, stale_after: TaskTime) -> Vec<Problem> {
+    let mut tasks = tasks.to_vec();
+    tasks.sort_by_key(|task| (task.start_time, task.id));
+
+    let mut problems = vec![];
+    for task in &tasks {
+        match task.status {
+            TaskStatus::Complete => match (task.total_time, task.end_time) {
+                (None, _) => problems.push(Problem::MissingTotal(task.clone())),
+                (Some(total), _) if total < 0 => {
+                    problems.push(Problem::NegativeTotal(task.clone()))
+                }
+                (Some(total), Some(end_time)) if total != end_time - task.start_time => {
+                    problems.push(Problem::WrongTotal(task.clone()))
+                }
+                _ => {}
+            },
+            TaskStatus::Active if now - task.start_time > stale_after.total_seconds() => {
+                problems.push(Problem::Stale(task.clone()))
+            }
+            TaskStatus::Active => {}
+        }
+    }
+
+    // Active tasks of the same name overlap too, but are reported as duplicates
+    let duplicate = |first: &Task, second: &Task| {
+        first.status == TaskStatus::Active
+            && second.status == TaskStatus::Active
+            && first.name == second.name
+    };
+    problems.extend(
+        overlaps(&tasks, now)
+            .into_iter()
+            .filter(|(first, second)| !duplicate(first, second))
+            .map(|(first, second)| Problem::Overlap(first, second)),
+    );
+
+    let mut active: BTreeMap<&str, Vec<Task>> = BTreeMap::new();
+    for task in tasks
+        .iter()
+        .filter(|task| task.status == TaskStatus::Active)
+    {
+        active.entry(&task.name).or_default().push(task.clone());
+    }
+    problems.extend(
+        active
+            .into_values()
+            .filter(|tasks| tasks.len() > 1)
+            .map(Problem::DuplicateActive),
+    );
+    problems
+}
+
+/// Every pair of tasks that ran at the same time, the one that started first first. Active
+/// tasks run until `now`.
+pub(crate) fn overlaps(tasks: &[Task], now: i64) -> Vec<(Task, Task)> {
+    let mut tasks: Vec<&Task> = tasks.iter().collect();
+    tasks.sort_by_key(|task| (task.start_time, task.id));
+
+    let mut pairs = vec![];
+    // Tasks that started earlier and may still be running
+    let mut running: Vec<&Task> = vec![];
+    for task in tasks {
+        running.retain(|earlier| end_time(earlier, now) > task.start_time);
+        pairs.extend(
+            running
+                .iter()
+                .map(|earlier| ((*earlier).clone(), task.clone())),
+        );
+        if end_time(task, now) > task.start_time {
+            running.push(task);
+        }
+    }
+    pairs
+}
+
+/// When `task` ended, or `now` while it is active. A completed task without an end time ends
+/// after its total time.
+pub fn end_time(task: &Task, now: i64) -> i64 {
+    match (task.end_time, task.status) {
+        (Some(end_time), _) => end_time,
+        (None, TaskStatus::Active) => now,
+        (None, TaskStatus::Complete) => task.start_time + task.total_time.unwrap_or(0),
+    }
+}
+
+fn ended(task: &Task, end_time: i64) -> Task {
+    Task {
+        end_time: Some(end_time),
+        total_time: Some(end_time - task.start_time),
+        status: TaskStatus::Complete,
+        ..task.clone()
+    }
+}
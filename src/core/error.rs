@@ -0,0 +1,59 @@
+use std::fmt::Display;
+
+/// Exit codes returned by the binary. Documented in the README, so only ever add to these.
+pub const EXIT_DATABASE: i32 = 1;
+// 2 is used by clap for invalid command line usage
+pub const EXIT_NO_ACTIVE: i32 = 3;
+pub const EXIT_NOT_FOUND: i32 = 4;
+pub const EXIT_CONFLICT: i32 = 5;
+pub const EXIT_VALIDATION: i32 = 6;
+pub const EXIT_PROBLEMS: i32 = 7;
+
+pub type Result<T> = std::result::Result<T, MetronomeError>;
+
+#[derive(Debug)]
+pub enum MetronomeError {
+    Database(rusqlite::Error), // The database could not be read or written
+    Storage(String),           // A file backed store could not be read or written
+    Validation(String),        // Input that cannot be stored, e.g. an empty task name
+    NotFound(String),          // The task the command refers to does not exist
+    Conflict(String),          // The command clashes with the current state, e.g. an active task
+}
+
+impl MetronomeError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MetronomeError::Database(_) | MetronomeError::Storage(_) => EXIT_DATABASE,
+            MetronomeError::Validation(_) => EXIT_VALIDATION,
+            MetronomeError::NotFound(_) => EXIT_NOT_FOUND,
+            MetronomeError::Conflict(_) => EXIT_CONFLICT,
+        }
+    }
+}
+
+impl Display for MetronomeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetronomeError::Database(e) => write!(f, "database error: {}", e),
+            MetronomeError::Storage(message) => write!(f, "storage error: {}", message),
+            MetronomeError::Validation(message)
+            | MetronomeError::NotFound(message)
+            | MetronomeError::Conflict(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for MetronomeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MetronomeError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MetronomeError {
+    fn from(e: rusqlite::Error) -> Self {
+        MetronomeError::Database(e)
+    }
+}
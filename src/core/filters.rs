@@ -1,8 +1,7 @@
 use std::str::FromStr;
-use std::string::ToString;
 use strum_macros::{Display, EnumIter, EnumString};
 
-#[derive(Debug, EnumIter, EnumString, Display, Copy, Clone)]
+#[derive(Debug, Default, EnumIter, EnumString, Display, Copy, Clone, PartialEq)]
 pub enum Filter {
     #[strum(
         serialize = "d",
@@ -47,18 +46,34 @@ pub enum Filter {
     )]
     Year,
 
-    #[strum(to_string = "No filter will be applied")]
+    #[default]
+    #[strum(
+        serialize = "a",
+        serialize = "all",
+        to_string = "No filter will be applied"
+    )]
     All,
 }
 
+impl Filter {
+    /// The long name the filter is given by, e.g. "week".
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Day => "day",
+            Filter::Week => "week",
+            Filter::Month => "month",
+            Filter::Quarter => "quarter",
+            Filter::SemiAnnual => "semiannual",
+            Filter::Year => "year",
+            Filter::All => "all",
+        }
+    }
+}
+
 impl From<Option<&String>> for Filter {
     fn from(value: Option<&String>) -> Self {
         match value {
-            Some(filter) => {
-                let filter = Filter::from_str(filter.as_str()).unwrap_or(Filter::All);
-                println!("** {} **", filter.to_string());
-                filter
-            }
+            Some(filter) => Filter::from_str(filter.as_str()).unwrap_or(Filter::All),
             None => Filter::All,
         }
     }
@@ -0,0 +1,103 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::NaiveTime;
+
+use super::doctor;
+use super::task::Task;
+use super::tasktime::TaskTime;
+
+/// The part of each day that is expected to be tracked, written e.g. "09:00-17:00".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorkHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime, // After the start
+}
+
+impl Default for WorkHours {
+    fn default() -> Self {
+        Self {
+            start: NaiveTime::from_hms_opt(9, 0, 0).unwrap_or(NaiveTime::MIN),
+            end: NaiveTime::from_hms_opt(17, 0, 0).unwrap_or(NaiveTime::MIN),
+        }
+    }
+}
+
+impl fmt::Display for WorkHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+impl FromStr for WorkHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid work hours \"{}\", expected e.g. \"09:00-17:00\"",
+                s
+            )
+        };
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let time =
+            |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+        let hours = WorkHours {
+            start: time(start)?,
+            end: time(end)?,
+        };
+        if hours.end <= hours.start {
+            return Err(invalid());
+        }
+        Ok(hours)
+    }
+}
+
+/// A period in which no task was running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    pub start: i64, // Unix timestamp
+    pub end: i64,   // Unix timestamp
+}
+
+impl Gap {
+    pub fn duration(&self) -> TaskTime {
+        TaskTime::from(self.end - self.start)
+    }
+}
+
+/// The gaps between `start` and `end` that none of `tasks` covers. Active tasks run until
+/// `now`, and nothing after `now` counts as a gap yet.
+pub(crate) fn find(tasks: &[Task], start: i64, end: i64, now: i64) -> Vec<Gap> {
+    let end = end.min(now);
+    let mut tasks: Vec<&Task> = tasks.iter().collect();
+    tasks.sort_by_key(|task| task.start_time);
+
+    let mut gaps = vec![];
+    // Everything before this is covered or already a gap
+    let mut covered = start;
+    for task in tasks {
+        if covered >= end {
+            break;
+        }
+        if task.start_time > covered {
+            gaps.push(Gap {
+                start: covered,
+                end: task.start_time.min(end),
+            });
+        }
+        covered = covered.max(doctor::end_time(task, now));
+    }
+    if covered < end {
+        gaps.push(Gap {
+            start: covered,
+            end,
+        });
+    }
+    gaps
+}
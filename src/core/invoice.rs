@@ -0,0 +1,323 @@
+use std::fmt::Write;
+
+use chrono::NaiveDate;
+use strum_macros::{Display, EnumString};
+
+use super::billing::{Amount, Billing, RoundingScope};
+use super::config::Config;
+use super::error::{MetronomeError, Result};
+use super::task::Task;
+use super::tasktime::TaskTime;
+
+/// How an invoice is written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum InvoiceFormat {
+    #[default]
+    #[strum(to_string = "markdown", serialize = "md")]
+    Markdown,
+    Html,
+    #[strum(to_string = "text", serialize = "txt")]
+    Text,
+}
+
+/// One line of an invoice: a task, or the rounding of a day's tasks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvoiceLine {
+    pub task_id: Option<i64>, // None for rounding
+    pub description: String,
+    pub time: TaskTime, // Billed time, after rounding per task
+    pub rate: f64,
+    pub amount: f64, // Rounded to cents
+}
+
+/// The lines of the tasks started on one day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvoiceDay {
+    pub date: NaiveDate,
+    pub lines: Vec<InvoiceLine>,
+}
+
+/// An itemized bill for the tasks of one client, i.e. one category.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invoice {
+    pub number: i64,
+    pub client: String,
+    pub date: NaiveDate,  // Day the invoice was made
+    pub since: NaiveDate, // First day of the billed period
+    pub until: NaiveDate, // Last day of the billed period
+    pub currency: String, // Empty when none is set
+    pub days: Vec<InvoiceDay>,
+    pub tax_rate: f64, // Percent
+}
+
+impl Invoice {
+    /// Total billed time.
+    pub fn time(&self) -> TaskTime {
+        self.lines().map(|line| line.time).sum()
+    }
+
+    pub fn subtotal(&self) -> f64 {
+        cents(self.lines().map(|line| line.amount).sum())
+    }
+
+    pub fn tax(&self) -> f64 {
+        cents(self.subtotal() * self.tax_rate / 100.0)
+    }
+
+    pub fn total(&self) -> f64 {
+        cents(self.subtotal() + self.tax())
+    }
+
+    pub fn render(&self, format: InvoiceFormat) -> String {
+        match format {
+            InvoiceFormat::Markdown => self.markdown(),
+            InvoiceFormat::Html => self.html(),
+            InvoiceFormat::Text => self.text(),
+        }
+    }
+
+    /// Every line, day by day.
+    pub fn lines(&self) -> impl Iterator<Item = &InvoiceLine> {
+        self.days.iter().flat_map(|day| day.lines.iter())
+    }
+
+    fn money(&self, value: f64) -> String {
+        Amount {
+            value,
+            currency: self.currency.clone(),
+        }
+        .to_string()
+    }
+
+    fn period(&self) -> String {
+        format!("{} to {}", self.since, self.until)
+    }
+
+    // Subtotal, tax and total, labelled
+    fn sums(&self) -> [(String, String); 3] {
+        [
+            (
+                format!("Subtotal ({} h)", hours(self.time())),
+                self.money(self.subtotal()),
+            ),
+            (format!("Tax ({}%)", self.tax_rate), self.money(self.tax())),
+            (String::from("Total"), self.money(self.total())),
+        ]
+    }
+
+    fn markdown(&self) -> String {
+        // Pipes would end a table cell
+        let cell = |text: &str| text.replace('|', "\\|");
+        let mut out = format!(
+            "# Invoice {}\n\n- Client: {}\n- Date: {}\n- Period: {}\n",
+            self.number,
+            self.client,
+            self.date,
+            self.period()
+        );
+        for day in &self.days {
+            let _ = write!(
+                out,
+                "\n## {}\n\n| Task | Hours | Rate | Amount |\n| --- | ---: | ---: | ---: |\n",
+                day.date
+            );
+            for line in &day.lines {
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} |",
+                    cell(&line.description),
+                    hours(line.time),
+                    self.money(line.rate),
+                    self.money(line.amount)
+                );
+            }
+        }
+        out.push_str("\n| | |\n| --- | ---: |\n");
+        for (label, value) in self.sums() {
+            let _ = writeln!(out, "| **{}** | **{}** |", label, value);
+        }
+        out
+    }
+
+    fn html(&self) -> String {
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+            <title>Invoice {0}</title>\n</head>\n<body>\n<h1>Invoice {0}</h1>\n<dl>\n\
+            <dt>Client</dt><dd>{1}</dd>\n<dt>Date</dt><dd>{2}</dd>\n\
+            <dt>Period</dt><dd>{3}</dd>\n</dl>\n<table>\n<thead>\n\
+            <tr><th>Task</th><th>Hours</th><th>Rate</th><th>Amount</th></tr>\n</thead>\n",
+            self.number,
+            escape(&self.client),
+            self.date,
+            self.period()
+        );
+        for day in &self.days {
+            let _ = writeln!(out, "<tbody>\n<tr><th colspan=\"4\">{}</th></tr>", day.date);
+            for line in &day.lines {
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&line.description),
+                    hours(line.time),
+                    escape(&self.money(line.rate)),
+                    escape(&self.money(line.amount))
+                );
+            }
+            out.push_str("</tbody>\n");
+        }
+        out.push_str("<tfoot>\n");
+        for (label, value) in self.sums() {
+            let _ = writeln!(
+                out,
+                "<tr><th colspan=\"3\">{}</th><td>{}</td></tr>",
+                escape(&label),
+                escape(&value)
+            );
+        }
+        out.push_str("</tfoot>\n</table>\n</body>\n</html>\n");
+        out
+    }
+
+    fn text(&self) -> String {
+        let mut out = format!(
+            "INVOICE {}\n\nClient: {}\nDate:   {}\nPeriod: {}\n",
+            self.number,
+            self.client,
+            self.date,
+            self.period()
+        );
+        let _ = write!(
+            out,
+            "\n{:<30} {:>8} {:>14} {:>14}\n",
+            "TASK", "HOURS", "RATE", "AMOUNT"
+        );
+        for day in &self.days {
+            let _ = write!(out, "\n{}\n", day.date);
+            for line in &day.lines {
+                let _ = writeln!(
+                    out,
+                    "  {:<28} {:>8} {:>14} {:>14}",
+                    line.description,
+                    hours(line.time),
+                    self.money(line.rate),
+                    self.money(line.amount)
+                );
+            }
+        }
+        out.push('\n');
+        for (label, value) in self.sums() {
+            let _ = writeln!(out, "{:<53} {:>14}", label, value);
+        }
+        out
+    }
+}
+
+/// The lines of an invoice for `client`, one per task, grouped by the day each task started
+/// on, and the currency they are billed in. Tasks must be completed, billable and in start
+/// order, and must all have a rate in the same currency.
+pub(crate) fn itemize(
+    client: &str,
+    tasks: &[Task],
+    config: &Config,
+) -> Result<(Vec<InvoiceDay>, String)> {
+    let rounding = config.rounding_for(client);
+    let mut currency: Option<String> = None;
+    let mut days: Vec<InvoiceDay> = vec![];
+    // Tasks of one day at one rate, rounded together when rounding per day
+    let mut groups: Vec<(NaiveDate, Billing, Vec<Task>)> = vec![];
+
+    for task in tasks {
+        let billing = config.billing_for(task);
+        let rate = billing.rate.ok_or_else(|| {
+            MetronomeError::Validation(format!(
+                "task {} \"{}\" has no rate, set one with e.g. `metronome config set \
+                category.{}.rate 85`",
+                task.id, task.name, client
+            ))
+        })?;
+        match &currency {
+            Some(currency) if *currency != billing.currency => {
+                return Err(MetronomeError::Validation(format!(
+                    "the tasks of one invoice must share a currency, found \"{}\" and \"{}\"",
+                    currency, billing.currency
+                )))
+            }
+            Some(_) => {}
+            None => currency = Some(billing.currency.clone()),
+        }
+
+        let date = config
+            .timezone
+            .date(task.start_time)
+            .unwrap_or(NaiveDate::MIN);
+        let recorded = TaskTime::from(task.total_time.unwrap_or(0));
+        let time = match rounding.scope {
+            RoundingScope::Task => rounding.round(recorded),
+            RoundingScope::Day => recorded,
+        };
+        let line = InvoiceLine {
+            task_id: Some(task.id),
+            description: task.name.clone(),
+            time,
+            rate,
+            amount: cents(time.hours() * rate),
+        };
+        match days.last_mut() {
+            Some(day) if day.date == date => day.lines.push(line),
+            _ => days.push(InvoiceDay {
+                date,
+                lines: vec![line],
+            }),
+        }
+        match groups
+            .iter_mut()
+            .find(|(group_date, group, _)| *group_date == date && *group == billing)
+        {
+            Some((_, _, tasks)) => tasks.push(task.clone()),
+            None => groups.push((date, billing, vec![task.clone()])),
+        }
+    }
+
+    // Rounding per day shows up as a line of its own under each day
+    if rounding.scope == RoundingScope::Day {
+        for (date, billing, tasks) in groups {
+            let recorded: TaskTime = tasks
+                .iter()
+                .map(|task| TaskTime::from(task.total_time.unwrap_or(0)))
+                .sum();
+            let difference = rounding.apply(&tasks, &config.timezone) - recorded;
+            if difference == TaskTime::ZERO {
+                continue;
+            }
+            let rate = billing.rate.unwrap_or(0.0);
+            if let Some(day) = days.iter_mut().find(|day| day.date == date) {
+                day.lines.push(InvoiceLine {
+                    task_id: None,
+                    description: format!("Rounding ({})", rounding),
+                    time: difference,
+                    rate,
+                    amount: cents(difference.hours() * rate),
+                });
+            }
+        }
+    }
+
+    Ok((days, currency.unwrap_or_default()))
+}
+
+fn cents(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// Decimal hours, as invoices usually give them
+fn hours(time: TaskTime) -> String {
+    format!("{:.2}", time.hours())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
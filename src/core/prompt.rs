@@ -0,0 +1,45 @@
+use super::tasktime::TaskTime;
+use super::timezone::DisplayZone;
+
+pub const DEFAULT_FORMAT: &str = "{name} ({elapsed:short})";
+
+/// The most recently started active task, as shown in a prompt.
+#[derive(Debug, PartialEq)]
+pub struct PromptTask {
+    pub name: String,
+    pub category: String,
+    pub start_time: i64,
+    pub active_count: i64, // Number of active tasks, including this one
+}
+
+/// Replaces the placeholders in `format` with values from `task`.
+///
+/// Supported placeholders are `{name}`, `{category}`, `{count}`, `{start}`,
+/// `{elapsed}` and `{elapsed:short}`. Unknown placeholders are left untouched.
+pub fn render(format: &str, task: &PromptTask, now: i64, zone: &DisplayZone) -> String {
+    let mut rendered = String::with_capacity(format.len());
+    let mut rest = format;
+
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}') else {
+            break;
+        };
+        rendered.push_str(&rest[..open]);
+        let placeholder = &rest[open + 1..open + close];
+        let elapsed = TaskTime::from(now - task.start_time);
+
+        match placeholder {
+            "name" => rendered.push_str(&task.name),
+            "category" => rendered.push_str(&task.category),
+            "count" => rendered.push_str(&task.active_count.to_string()),
+            "start" => rendered.push_str(&zone.format(task.start_time, None, "%H:%M")),
+            "elapsed" => rendered.push_str(&elapsed.to_string()),
+            "elapsed:short" => rendered.push_str(&elapsed.short()),
+            _ => rendered.push_str(&rest[open..=open + close]),
+        }
+        rest = &rest[open + close + 1..];
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
@@ -0,0 +1,750 @@
+pub mod jsonl;
+pub mod memory;
+pub mod sqlite;
+
+use std::cmp::Ordering;
+use std::path::Path;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+use super::error::{MetronomeError, Result};
+use super::task::{Task, TaskStatus};
+use super::tasktime::TaskTime;
+use super::CategoryTotal;
+
+use jsonl::JsonlStore;
+use sqlite::SqliteStore;
+
+/// What tasks are sorted by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Id,
+    Start,
+    End,      // Tasks without an end time come last in either direction
+    Duration, // Total time, active tasks come last in either direction
+    Name,
+    Category,
+}
+
+/// Order of the tasks returned by a query. Ties are broken by ID in the same direction.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TaskOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl TaskOrder {
+    pub fn asc(key: SortKey) -> Self {
+        Self {
+            key,
+            descending: false,
+        }
+    }
+
+    pub fn desc(key: SortKey) -> Self {
+        Self {
+            key,
+            descending: true,
+        }
+    }
+
+    /// Compares two tasks the way stores order them.
+    pub fn compare(&self, a: &Task, b: &Task) -> Ordering {
+        // Missing values sort last whatever the direction
+        let missing_last = |a: Option<i64>, b: Option<i64>| a.is_none().cmp(&b.is_none());
+        let (missing, ordering) = match self.key {
+            SortKey::Id => (Ordering::Equal, Ordering::Equal),
+            SortKey::Start => (Ordering::Equal, a.start_time.cmp(&b.start_time)),
+            SortKey::End => (
+                missing_last(a.end_time, b.end_time),
+                a.end_time.cmp(&b.end_time),
+            ),
+            SortKey::Duration => (
+                missing_last(a.total_time, b.total_time),
+                a.total_time.cmp(&b.total_time),
+            ),
+            SortKey::Name => (Ordering::Equal, a.name.cmp(&b.name)),
+            SortKey::Category => (Ordering::Equal, a.category.cmp(&b.category)),
+        };
+        let ordering = ordering.then(a.id.cmp(&b.id));
+        missing.then(if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        })
+    }
+}
+
+/// Selects tasks from a store. Fields that are `None` match every task.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TaskQuery {
+    pub status: Option<TaskStatus>,
+    pub name: Option<String>, // Exact name
+    pub name_pattern: Option<NamePattern>,
+    pub categories: Vec<String>, // Any of these, every category when empty
+    pub excluded_categories: Vec<String>,
+    pub started_after: Option<i64>,  // Exclusive
+    pub started_before: Option<i64>, // Exclusive
+    pub min_duration: Option<i64>,   // Seconds, inclusive
+    pub max_duration: Option<i64>,   // Seconds, inclusive
+    // Active tasks count up to this time for the duration filters. Without it they have no
+    // duration and never match a duration filter.
+    pub now: Option<i64>,
+    pub order: TaskOrder,
+    pub limit: Option<usize>,
+    pub offset: usize, // Number of matching tasks to skip, in order
+}
+
+impl TaskQuery {
+    pub fn matches(&self, task: &Task) -> bool {
+        let duration = task
+            .total_time
+            .or(self.now.map(|now| now - task.start_time));
+        self.status.is_none_or(|status| task.status == status)
+            && self.name.as_ref().is_none_or(|name| &task.name == name)
+            && self
+                .name_pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches(&task.name))
+            && (self.categories.is_empty() || self.categories.contains(&task.category))
+            && !self.excluded_categories.contains(&task.category)
+            && self
+                .started_after
+                .is_none_or(|started_after| task.start_time > started_after)
+            && self
+                .started_before
+                .is_none_or(|started_before| task.start_time < started_before)
+            && self
+                .min_duration
+                .is_none_or(|min| duration.is_some_and(|duration| duration >= min))
+            && self
+                .max_duration
+                .is_none_or(|max| duration.is_some_and(|duration| duration <= max))
+    }
+
+    /// Runs the query over tasks held in memory.
+    pub fn apply<'a>(&self, tasks: impl Iterator<Item = &'a Task>) -> Vec<Task> {
+        let mut selected: Vec<Task> = tasks.filter(|task| self.matches(task)).cloned().collect();
+        selected.sort_by(|a, b| self.order.compare(a, b));
+        selected.drain(..self.offset.min(selected.len()));
+        if let Some(limit) = self.limit {
+            selected.truncate(limit);
+        }
+        selected
+    }
+}
+
+/// Matches task names with a glob such as `fix-*`, or a regular expression between slashes
+/// such as `/^fix-\d+$/`. Globs use `*`, `?` and `[...]` like SQLite's GLOB, and both are
+/// case sensitive.
+#[derive(Debug, Clone)]
+pub struct NamePattern {
+    source: String, // The glob, or the regex without its slashes
+    glob: bool,
+    regex: Regex,
+}
+
+impl NamePattern {
+    pub fn matches(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.glob
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl PartialEq for NamePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.glob == other.glob && self.source == other.source
+    }
+}
+
+impl FromStr for NamePattern {
+    type Err = MetronomeError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let regex = s
+            .strip_prefix('/')
+            .and_then(|s| s.strip_suffix('/'))
+            .filter(|regex| !regex.is_empty());
+        let (source, glob, pattern) = match regex {
+            Some(regex) => (regex, false, regex.to_string()),
+            None => (s, true, glob_to_regex(s)?),
+        };
+        let regex = Regex::new(&pattern)
+            .map_err(|e| MetronomeError::Validation(format!("invalid pattern {}: {}", s, e)))?;
+        Ok(Self {
+            source: String::from(source),
+            glob,
+            regex,
+        })
+    }
+}
+
+// Follows SQLite's GLOB, so the memory and JSON lines stores match the same names
+fn glob_to_regex(glob: &str) -> Result<String> {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                if chars.next_if_eq(&'^').is_some() {
+                    regex.push('^');
+                }
+                // A ] straight after the opening bracket is part of the class
+                if chars.next_if_eq(&']').is_some() {
+                    regex.push_str("\\]");
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('-') => regex.push('-'),
+                        Some(c) => regex.push_str(&regex::escape(&c.to_string())),
+                        None => {
+                            return Err(MetronomeError::Validation(format!(
+                                "invalid pattern {}: unclosed [",
+                                glob
+                            )))
+                        }
+                    }
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Ok(regex)
+}
+
+/// A change to a locked task, or to the lock itself, that was made with `--force`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForcedChange {
+    pub task_id: Option<i64>, // None when the lock was moved back
+    pub command: String,      // e.g. "end" or "sync"
+    pub changed_at: i64,
+}
+
+/// One insert or update of a task, as kept in the task's history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub task_id: i64,
+    pub command: String, // e.g. "start" or "end"
+    pub user: String,
+    pub changed_at: i64,
+    pub old: Option<Task>, // None when the task was created
+    // The status before the change when it was not a known one, which `old` cannot hold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_status: Option<String>,
+    pub new: Task,
+}
+
+/// A stored task whose status is neither Active nor Complete, e.g. after the store was edited
+/// by hand. It cannot be read as a `Task` until its status is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownStatus {
+    pub id: i64,
+    pub name: String,
+    pub start_time: i64,
+    pub status: String,
+    pub end_time: Option<i64>,
+}
+
+/// Storage of tasks. Implemented for SQLite, JSON lines and memory.
+pub trait TaskStore {
+    /// Stores a new task. The ID of `task` is ignored, and the stored task is returned
+    /// with its assigned ID, and a new UUID if it did not have one.
+    fn insert(&mut self, task: Task) -> Result<Task>;
+
+    /// Replaces the stored task with the same ID.
+    fn update(&mut self, task: &Task) -> Result<()>;
+
+    fn get(&self, id: i64) -> Result<Option<Task>>;
+
+    fn query(&self, query: &TaskQuery) -> Result<Vec<Task>>;
+
+    /// Total time of the completed tasks matching `query` per category, largest first. Stores
+    /// know nothing of rounding, so the rounded time is the total time.
+    fn totals(&self, query: &TaskQuery) -> Result<Vec<CategoryTotal>> {
+        let mut totals: Vec<CategoryTotal> = vec![];
+        for task in self.query(query)? {
+            let time = TaskTime::from(task.total_time.unwrap_or(0));
+            match totals
+                .iter_mut()
+                .find(|total| total.category == task.category)
+            {
+                Some(total) => {
+                    total.time += time;
+                    total.rounded += time;
+                }
+                None => totals.push(CategoryTotal {
+                    category: task.category,
+                    time,
+                    rounded: time,
+                    billed: None,
+                }),
+            }
+        }
+        totals.sort_by_key(|total| std::cmp::Reverse(total.time));
+        Ok(totals)
+    }
+
+    /// Reserves the number of a new invoice for `client`, one higher than any before it.
+    /// Stores without a record of invoices count on from the highest number on a task.
+    fn next_invoice_number(&mut self, _client: &str, _created_at: i64) -> Result<i64> {
+        let tasks = self.query(&TaskQuery::default())?;
+        Ok(tasks
+            .iter()
+            .filter_map(|task| task.invoice)
+            .max()
+            .unwrap_or(0)
+            + 1)
+    }
+
+    /// Tasks started before this timestamp are locked, see `Tracker::lock`.
+    fn locked_until(&self) -> Result<Option<i64>>;
+
+    /// Moves the lock to `until`, forward or back.
+    fn lock(&mut self, until: i64) -> Result<()>;
+
+    fn log_forced_change(&mut self, change: &ForcedChange) -> Result<()>;
+
+    /// Every forced change, oldest first.
+    fn forced_changes(&self) -> Result<Vec<ForcedChange>>;
+
+    /// Adds to the history of a task. The history is only ever appended to.
+    fn log_change(&mut self, change: &Change) -> Result<()>;
+
+    /// The history of the task with ID `task_id`, oldest first.
+    fn history(&self, task_id: i64) -> Result<Vec<Change>>;
+
+    /// Tasks with a status other than Active or Complete. Only stores edited outside of
+    /// metronome can have them, and the other stores reject them when they are read.
+    fn unknown_statuses(&self) -> Result<Vec<UnknownStatus>> {
+        Ok(vec![])
+    }
+
+    /// Sets the status of the task with ID `id`, which may be unknown.
+    fn set_status(&mut self, id: i64, status: TaskStatus) -> Result<()> {
+        match self.get(id)? {
+            Some(task) => self.update(&Task { status, ..task }),
+            None => Err(MetronomeError::NotFound(format!(
+                "there is no task with ID {}",
+                id
+            ))),
+        }
+    }
+
+    /// The most recently started active task, and the number of active tasks.
+    fn latest_active(&self) -> Result<Option<(Task, usize)>> {
+        let active = self.query(&TaskQuery {
+            status: Some(TaskStatus::Active),
+            order: TaskOrder::desc(SortKey::Start),
+            ..Default::default()
+        })?;
+        let count = active.len();
+        Ok(active.into_iter().next().map(|task| (task, count)))
+    }
+
+    /// Starts a transaction. Changes are only kept once `commit` is called.
+    fn begin(&mut self) -> Result<()>;
+
+    fn commit(&mut self) -> Result<()>;
+
+    fn rollback(&mut self) -> Result<()>;
+
+    fn close(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens the store at `path`, creating it if needed. Paths ending in `.jsonl` use the
+/// JSON lines backend, everything else is a SQLite database.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn TaskStore>> {
+    if is_jsonl(path.as_ref()) {
+        Ok(Box::new(JsonlStore::open(path)?))
+    } else {
+        Ok(Box::new(SqliteStore::open(path)?))
+    }
+}
+
+/// Opens an existing store without creating or migrating anything.
+pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Box<dyn TaskStore>> {
+    if is_jsonl(path.as_ref()) {
+        Ok(Box::new(JsonlStore::open_read_only(path)?))
+    } else {
+        Ok(Box::new(SqliteStore::open_read_only(path)?))
+    }
+}
+
+/// Gives `task` a new random UUID unless it already has one.
+fn with_uuid(task: Task) -> Task {
+    if !task.uuid.is_empty() {
+        return task;
+    }
+    Task {
+        uuid: uuid::Uuid::new_v4().to_string(),
+        ..task
+    }
+}
+
+fn is_jsonl(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "jsonl")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::memory::MemoryStore;
+    use super::*;
+
+    fn task(name: &str, start_time: i64, category: &str, status: TaskStatus) -> Task {
+        let complete = status == TaskStatus::Complete;
+        Task {
+            id: 0,
+            name: String::from(name),
+            start_time,
+            end_time: complete.then_some(start_time + 60),
+            total_time: complete.then_some(60),
+            category: String::from(category),
+            status,
+            tags: vec![String::from("billable")],
+            utc_offset: Some(3600),
+            uuid: String::new(),
+            modified_at: start_time,
+            invoice: None,
+        }
+    }
+
+    #[test]
+    fn test_name_pattern() {
+        let glob: NamePattern = "fix-[0-9]*".parse().unwrap();
+        assert!(glob.is_glob());
+        assert!(glob.matches("fix-12"));
+        assert!(!glob.matches("fix-x"));
+        assert!(!glob.matches("prefix-1"));
+        let glob: NamePattern = "a.b?".parse().unwrap();
+        assert!(glob.matches("a.bc"));
+        assert!(!glob.matches("axbc"));
+
+        let regex: NamePattern = r"/^fix-\d+$/".parse().unwrap();
+        assert!(!regex.is_glob());
+        assert_eq!(r"^fix-\d+$", regex.as_str());
+        assert!(regex.matches("fix-12"));
+        assert!(!regex.matches("fix-12a"));
+        assert!("/(/".parse::<NamePattern>().is_err());
+    }
+
+    #[test]
+    fn test_glob_classes() {
+        let glob: NamePattern = "[]x]*".parse().unwrap();
+        assert!(glob.matches("]a"));
+        assert!(glob.matches("xa"));
+        assert!(!glob.matches("a"));
+        let glob: NamePattern = "[^]x]".parse().unwrap();
+        assert!(glob.matches("a"));
+        assert!(!glob.matches("]"));
+        let glob: NamePattern = "[[.&&]".parse().unwrap();
+        assert!(glob.matches("["));
+        assert!(glob.matches("&"));
+        assert!(!glob.matches("a"));
+
+        for malformed in ["fix-[0-9", "[]", "[^]"] {
+            let error = malformed.parse::<NamePattern>().unwrap_err();
+            assert_eq!(6, error.exit_code());
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("metronome_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    // Every backend has to behave the same, so they all run the same checks
+    fn check_store(store: &mut dyn TaskStore) -> Result<()> {
+        let a = store.insert(task("A", 300, "Work", TaskStatus::Complete))?;
+        let b = store.insert(task("B", 100, "Misc", TaskStatus::Active))?;
+        let c = store.insert(task("C", 200, "Work", TaskStatus::Complete))?;
+        assert!(a.id < b.id && b.id < c.id);
+        assert_eq!(Some(b.clone()), store.get(b.id)?);
+        assert_eq!(None, store.get(999)?);
+
+        let names = |tasks: Vec<Task>| tasks.into_iter().map(|task| task.name).collect::<Vec<_>>();
+        assert_eq!(
+            vec!["A", "B", "C"],
+            names(store.query(&TaskQuery::default())?)
+        );
+        let query = TaskQuery {
+            categories: vec![String::from("Work")],
+            order: TaskOrder::asc(SortKey::Start),
+            ..Default::default()
+        };
+        assert_eq!(vec!["C", "A"], names(store.query(&query)?));
+        let query = TaskQuery {
+            categories: vec![String::from("Work"), String::from("Misc")],
+            excluded_categories: vec![String::from("Misc")],
+            ..Default::default()
+        };
+        assert_eq!(vec!["A", "C"], names(store.query(&query)?));
+        let query = TaskQuery {
+            excluded_categories: vec![String::from("Work")],
+            ..Default::default()
+        };
+        assert_eq!(vec!["B"], names(store.query(&query)?));
+        let query = TaskQuery {
+            name_pattern: Some("[AB]*".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(vec!["A", "B"], names(store.query(&query)?));
+        let query = TaskQuery {
+            name_pattern: Some("/^[BC]$/".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(vec!["B", "C"], names(store.query(&query)?));
+        // The active task has run for 300 seconds by now, and has no duration without it
+        let query = TaskQuery {
+            min_duration: Some(61),
+            now: Some(400),
+            ..Default::default()
+        };
+        assert_eq!(vec!["B"], names(store.query(&query)?));
+        let query = TaskQuery {
+            min_duration: Some(60),
+            ..Default::default()
+        };
+        assert_eq!(vec!["A", "C"], names(store.query(&query)?));
+        let query = TaskQuery {
+            min_duration: Some(30),
+            max_duration: Some(60),
+            now: Some(400),
+            ..Default::default()
+        };
+        assert_eq!(vec!["A", "C"], names(store.query(&query)?));
+        let query = TaskQuery {
+            started_after: Some(100),
+            order: TaskOrder::desc(SortKey::Start),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(vec!["A"], names(store.query(&query)?));
+        let query = TaskQuery {
+            started_after: Some(100),
+            started_before: Some(300),
+            ..Default::default()
+        };
+        assert_eq!(vec!["C"], names(store.query(&query)?));
+        let query = TaskQuery {
+            order: TaskOrder::desc(SortKey::Start),
+            limit: Some(1),
+            offset: 1,
+            ..Default::default()
+        };
+        assert_eq!(vec!["C"], names(store.query(&query)?));
+        let query = TaskQuery {
+            order: TaskOrder::desc(SortKey::End),
+            ..Default::default()
+        };
+        assert_eq!(vec!["A", "C", "B"], names(store.query(&query)?));
+        let query = TaskQuery {
+            order: TaskOrder::asc(SortKey::Duration),
+            ..Default::default()
+        };
+        assert_eq!(vec!["A", "C", "B"], names(store.query(&query)?));
+        let query = TaskQuery {
+            order: TaskOrder::desc(SortKey::Category),
+            ..Default::default()
+        };
+        assert_eq!(vec!["C", "A", "B"], names(store.query(&query)?));
+
+        let totals = store.totals(&TaskQuery::default())?;
+        assert_eq!(
+            vec![
+                CategoryTotal {
+                    category: String::from("Work"),
+                    time: TaskTime::from(120),
+                    rounded: TaskTime::from(120),
+                    billed: None,
+                },
+                CategoryTotal {
+                    category: String::from("Misc"),
+                    time: TaskTime::from(0),
+                    rounded: TaskTime::from(0),
+                    billed: None,
+                },
+            ],
+            totals
+        );
+        assert_eq!(Some((b.clone(), 1)), store.latest_active()?);
+
+        let ended = Task {
+            end_time: Some(400),
+            total_time: Some(300),
+            status: TaskStatus::Complete,
+            ..b
+        };
+        store.update(&ended)?;
+        assert_eq!(Some(ended), store.get(b.id)?);
+        assert_eq!(None, store.latest_active()?);
+
+        // Locks and forced changes are kept, and rolled back like tasks
+        assert_eq!(None, store.locked_until()?);
+        let change = ForcedChange {
+            task_id: Some(a.id),
+            command: String::from("end"),
+            changed_at: 400,
+        };
+        store.begin()?;
+        store.lock(300)?;
+        store.log_forced_change(&change)?;
+        store.rollback()?;
+        assert_eq!(None, store.locked_until()?);
+        assert!(store.forced_changes()?.is_empty());
+        store.lock(300)?;
+        store.lock(200)?;
+        store.log_forced_change(&change)?;
+        assert_eq!(Some(200), store.locked_until()?);
+        assert_eq!(vec![change], store.forced_changes()?);
+
+        // Invoice numbers count up, with billed tasks keeping theirs
+        let number = store.next_invoice_number("Work", 400)?;
+        assert_eq!(1, number);
+        let billed = Task {
+            invoice: Some(number),
+            ..c.clone()
+        };
+        store.update(&billed)?;
+        assert_eq!(Some(billed.clone()), store.get(c.id)?);
+        assert_eq!(2, store.next_invoice_number("Work", 400)?);
+
+        // Numbers synced from another store are never issued again
+        store.update(&Task {
+            invoice: Some(5),
+            ..billed.clone()
+        })?;
+        assert_eq!(6, store.next_invoice_number("Work", 400)?);
+        store.update(&billed)?;
+
+        // The history of a task is kept oldest first, and rolled back like tasks
+        let created = Change {
+            task_id: c.id,
+            command: String::from("start"),
+            user: String::from("alice"),
+            changed_at: 250,
+            old: None,
+            old_status: None,
+            new: c.clone(),
+        };
+        let invoiced = Change {
+            command: String::from("invoice"),
+            changed_at: 400,
+            old: Some(c.clone()),
+            new: billed.clone(),
+            ..created.clone()
+        };
+        store.begin()?;
+        store.log_change(&created)?;
+        store.rollback()?;
+        assert!(store.history(c.id)?.is_empty());
+        store.log_change(&created)?;
+        store.log_change(&invoiced)?;
+        assert_eq!(vec![created, invoiced], store.history(c.id)?);
+        assert!(store.history(a.id)?.is_empty());
+
+        // Nothing in a rolled back transaction is kept
+        store.begin()?;
+        store.insert(task("D", 500, "Misc", TaskStatus::Active))?;
+        store.rollback()?;
+        assert_eq!(3, store.query(&TaskQuery::default())?.len());
+
+        store.begin()?;
+        store.insert(task("D", 500, "Misc", TaskStatus::Active))?;
+        store.commit()?;
+        assert_eq!(4, store.query(&TaskQuery::default())?.len());
+
+        let missing = Task { id: 999, ..a };
+        assert!(matches!(
+            store.update(&missing),
+            Err(crate::core::error::MetronomeError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_store() -> Result<()> {
+        check_store(&mut MemoryStore::new())
+    }
+
+    #[test]
+    fn test_sqlite_store() -> Result<()> {
+        check_store(&mut SqliteStore::open_in_memory()?)
+    }
+
+    #[test]
+    fn test_sqlite_unknown_status() -> Result<()> {
+        let path = temp_path("unknown_status.db");
+        let mut store = SqliteStore::open(&path)?;
+        let a = store.insert(task("A", 100, "Work", TaskStatus::Complete))?;
+        store.insert(task("B", 200, "Work", TaskStatus::Active))?;
+
+        // As left by editing the database by hand
+        rusqlite::Connection::open(&path)?
+            .execute("UPDATE tasks SET status = 'Done' WHERE id = ?1", [a.id])?;
+        assert!(store.get(a.id).is_err());
+        let unknown = UnknownStatus {
+            id: a.id,
+            name: String::from("A"),
+            start_time: a.start_time,
+            status: String::from("Done"),
+            end_time: a.end_time,
+        };
+        assert_eq!(vec![unknown], store.unknown_statuses()?);
+
+        store.set_status(a.id, TaskStatus::Complete)?;
+        assert!(store.unknown_statuses()?.is_empty());
+        assert_eq!(Some(a.clone()), store.get(a.id)?);
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonl_store() -> Result<()> {
+        let path = temp_path("store.jsonl");
+        let mut store = open(&path)?;
+        check_store(store.as_mut())?;
+
+        // The file holds everything needed to rebuild the store
+        let reopened = open(&path)?;
+        assert_eq!(
+            store.query(&TaskQuery::default())?,
+            reopened.query(&TaskQuery::default())?
+        );
+
+        // Changes are appended, never rewritten: 3 inserts, 1 update, 2 locks, 1 forced change,
+        // 3 updates, 2 changes of history, then 1 committed insert
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(13, contents.lines().count());
+        assert_eq!(Some(200), reopened.locked_until()?);
+        assert_eq!(1, reopened.forced_changes()?.len());
+        assert_eq!(2, reopened.history(3)?.len());
+
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::error::{MetronomeError, Result};
+use crate::core::task::Task;
+
+use super::memory::MemoryStore;
+use super::{Change, ForcedChange, TaskQuery, TaskStore};
+
+// Lines that hold something other than a task, told apart by their only key
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Lock { locked_until: i64 },
+    ForcedChange { forced_change: ForcedChange },
+    Change { change: Box<Change> },
+}
+
+/// Stores tasks in an append-only text file with one JSON object per line.
+///
+/// Every insert or update appends the full task, and the last line for an ID wins when the
+/// file is read. Existing lines never change, so the file diffs and merges cleanly in git.
+/// Locks, forced changes and the history of each task are appended as lines of their own.
+///
+/// Transactions hold an exclusive lock on the file and start from a fresh read of it, so
+/// several processes can write to the same file without losing each other's changes.
+#[derive(Debug)]
+pub struct JsonlStore {
+    path: PathBuf,
+    tasks: MemoryStore,
+    pending: Vec<String>, // Lines not yet written to the file
+    lock: Option<File>,   // The locked file while in a transaction
+    read_only: bool,
+}
+
+impl JsonlStore {
+    /// Reads the tasks in `path`. The file is created on the first change.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut store = Self {
+            path: path.as_ref().to_path_buf(),
+            tasks: read_shared(path.as_ref())?,
+            pending: vec![],
+            lock: None,
+            read_only: false,
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    /// Reads the tasks in `path` without ever writing to it.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            tasks: read_shared(path.as_ref())?,
+            pending: vec![],
+            lock: None,
+            read_only: true,
+        })
+    }
+
+    // Lines written before tasks had UUIDs are given one, like the SQLite migrations
+    fn migrate(&mut self) -> Result<()> {
+        let outdated = |tasks: &MemoryStore| -> Result<Vec<Task>> {
+            let tasks = tasks.query(&TaskQuery::default())?;
+            Ok(tasks
+                .into_iter()
+                .filter(|task| task.uuid.is_empty())
+                .collect())
+        };
+        if outdated(&self.tasks)?.is_empty() {
+            return Ok(());
+        }
+
+        self.begin()?;
+        // Another process may have migrated the file in the meantime
+        for task in outdated(&self.tasks)? {
+            self.update(&Task {
+                uuid: uuid::Uuid::new_v4().to_string(),
+                modified_at: task.end_time.unwrap_or(task.start_time),
+                ..task
+            })?;
+        }
+        self.commit()
+    }
+
+    fn record<T: Serialize>(&mut self, line: &T) -> Result<()> {
+        if self.read_only {
+            return Err(self.error(String::from("opened read-only")));
+        }
+        let line =
+            serde_json::to_string(line).map_err(|e| MetronomeError::Storage(e.to_string()))?;
+        self.pending.push(line);
+        if self.lock.is_some() {
+            return Ok(());
+        }
+
+        let mut file = self.open_locked()?;
+        self.flush(&mut file)
+    }
+
+    fn open_locked(&self) -> Result<File> {
+        if self.read_only {
+            return Err(self.error(String::from("opened read-only")));
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| self.error(e.to_string()))?;
+        file.lock().map_err(|e| self.error(e.to_string()))?;
+        Ok(file)
+    }
+
+    fn flush(&mut self, file: &mut File) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for line in &self.pending {
+            lines.push_str(line);
+            lines.push('\n');
+        }
+
+        // A single write keeps the lines of a transaction together
+        file.write_all(lines.as_bytes())
+            .map_err(|e| self.error(e.to_string()))?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn error(&self, message: String) -> MetronomeError {
+        MetronomeError::Storage(format!("{}: {}", self.path.display(), message))
+    }
+}
+
+impl TaskStore for JsonlStore {
+    fn insert(&mut self, task: Task) -> Result<Task> {
+        let task = self.tasks.insert(task)?;
+        self.record(&task)?;
+        Ok(task)
+    }
+
+    fn update(&mut self, task: &Task) -> Result<()> {
+        self.tasks.update(task)?;
+        self.record(task)
+    }
+
+    fn get(&self, id: i64) -> Result<Option<Task>> {
+        self.tasks.get(id)
+    }
+
+    fn query(&self, query: &TaskQuery) -> Result<Vec<Task>> {
+        self.tasks.query(query)
+    }
+
+    fn locked_until(&self) -> Result<Option<i64>> {
+        self.tasks.locked_until()
+    }
+
+    fn lock(&mut self, until: i64) -> Result<()> {
+        self.tasks.lock(until)?;
+        self.record(&Record::Lock {
+            locked_until: until,
+        })
+    }
+
+    fn log_forced_change(&mut self, change: &ForcedChange) -> Result<()> {
+        self.tasks.log_forced_change(change)?;
+        self.record(&Record::ForcedChange {
+            forced_change: change.clone(),
+        })
+    }
+
+    fn forced_changes(&self) -> Result<Vec<ForcedChange>> {
+        self.tasks.forced_changes()
+    }
+
+    fn log_change(&mut self, change: &Change) -> Result<()> {
+        self.tasks.log_change(change)?;
+        self.record(&Record::Change {
+            change: Box::new(change.clone()),
+        })
+    }
+
+    fn history(&self, task_id: i64) -> Result<Vec<Change>> {
+        self.tasks.history(task_id)
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        let file = self.open_locked()?;
+        // Pick up the changes other processes made since the file was read
+        let contents = fs::read_to_string(&self.path).map_err(|e| self.error(e.to_string()))?;
+        self.tasks = parse(&self.path, &contents)?;
+        self.tasks.begin()?;
+        self.lock = Some(file);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        // Dropping the file releases the lock
+        let Some(mut file) = self.lock.take() else {
+            return Ok(());
+        };
+        self.tasks.commit()?;
+        self.flush(&mut file)
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.lock = None;
+        self.pending.clear();
+        self.tasks.rollback()
+    }
+}
+
+// Reads the file while no other process is writing to it
+fn read_shared(path: &Path) -> Result<MemoryStore> {
+    let error = |e: std::io::Error| MetronomeError::Storage(format!("{}: {}", path.display(), e));
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(MemoryStore::new()),
+        Err(e) => return Err(error(e)),
+    };
+    file.lock_shared().map_err(error)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).map_err(error)?;
+    parse(path, &contents)
+}
+
+fn parse(path: &Path, contents: &str) -> Result<MemoryStore> {
+    let mut tasks = MemoryStore::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |e: serde_json::Error| {
+            MetronomeError::Storage(format!("{} line {}: {}", path.display(), number + 1, e))
+        };
+        // A line that is neither is reported as a broken task, by far the most common line
+        match serde_json::from_str::<Task>(line) {
+            Ok(task) => tasks.load(task),
+            Err(e) => match serde_json::from_str::<Record>(line).map_err(|_| error(e))? {
+                Record::Lock { locked_until } => tasks.lock(locked_until)?,
+                Record::ForcedChange { forced_change } => {
+                    tasks.log_forced_change(&forced_change)?
+                }
+                Record::Change { change } => tasks.log_change(&change)?,
+            },
+        }
+    }
+    Ok(tasks)
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use crate::core::error::{MetronomeError, Result};
+use crate::core::task::Task;
+
+use super::{with_uuid, Change, ForcedChange, TaskQuery, TaskStore};
+
+/// Keeps tasks in memory only. Useful for tests and as the base of file backed stores.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore {
+    tasks: Vec<Task>,
+    positions: HashMap<i64, usize>, // Index in `tasks` of each ID, so loading is not quadratic
+    locked_until: Option<i64>,
+    forced_changes: Vec<ForcedChange>,
+    history: Vec<Change>,
+    snapshot: Option<Box<MemoryStore>>, // State to return to on rollback
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces a task keeping its ID, e.g. when loading from a file.
+    pub(crate) fn load(&mut self, task: Task) {
+        match self.positions.get(&task.id) {
+            Some(&position) => self.tasks[position] = task,
+            None => self.push(task),
+        }
+    }
+
+    fn push(&mut self, task: Task) {
+        self.positions.insert(task.id, self.tasks.len());
+        self.tasks.push(task);
+    }
+
+    fn next_id(&self) -> i64 {
+        self.tasks.iter().map(|task| task.id).max().unwrap_or(0) + 1
+    }
+}
+
+impl TaskStore for MemoryStore {
+    fn insert(&mut self, task: Task) -> Result<Task> {
+        let task = Task {
+            id: self.next_id(),
+            ..with_uuid(task)
+        };
+        self.push(task.clone());
+        Ok(task)
+    }
+
+    fn update(&mut self, task: &Task) -> Result<()> {
+        match self.positions.get(&task.id) {
+            Some(&position) => {
+                self.tasks[position] = task.clone();
+                Ok(())
+            }
+            None => Err(MetronomeError::NotFound(format!(
+                "there is no task with ID {}",
+                task.id
+            ))),
+        }
+    }
+
+    fn get(&self, id: i64) -> Result<Option<Task>> {
+        Ok(self
+            .positions
+            .get(&id)
+            .map(|&position| self.tasks[position].clone()))
+    }
+
+    fn query(&self, query: &TaskQuery) -> Result<Vec<Task>> {
+        Ok(query.apply(self.tasks.iter()))
+    }
+
+    fn locked_until(&self) -> Result<Option<i64>> {
+        Ok(self.locked_until)
+    }
+
+    fn lock(&mut self, until: i64) -> Result<()> {
+        self.locked_until = Some(until);
+        Ok(())
+    }
+
+    fn log_forced_change(&mut self, change: &ForcedChange) -> Result<()> {
+        self.forced_changes.push(change.clone());
+        Ok(())
+    }
+
+    fn forced_changes(&self) -> Result<Vec<ForcedChange>> {
+        Ok(self.forced_changes.clone())
+    }
+
+    fn log_change(&mut self, change: &Change) -> Result<()> {
+        self.history.push(change.clone());
+        Ok(())
+    }
+
+    fn history(&self, task_id: i64) -> Result<Vec<Change>> {
+        Ok(self
+            .history
+            .iter()
+            .filter(|change| change.task_id == task_id)
+            .cloned()
+            .collect())
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        self.snapshot = Some(Box::new(Self {
+            snapshot: None,
+            ..self.clone()
+        }));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.snapshot = None;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        if let Some(snapshot) = self.snapshot.take() {
+            *self = *snapshot;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,597 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use regex::Regex;
+use rusqlite::functions::FunctionFlags;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Value, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Row, ToSql};
+
+use crate::core::error::{MetronomeError, Result};
+use crate::core::task::{Task, TaskStatus};
+use crate::core::tasktime::TaskTime;
+use crate::core::CategoryTotal;
+
+use super::{with_uuid, Change, ForcedChange, SortKey, TaskQuery, TaskStore, UnknownStatus};
+
+// Schema changes applied after the tasks table is created, tracked with PRAGMA user_version.
+// Only ever append to this list.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE tasks ADD COLUMN tags TEXT",
+    // UTC offset in seconds of the zone the task was started in, NULL for older tasks
+    "ALTER TABLE tasks ADD COLUMN utc_offset INTEGER",
+    // Stable IDs for syncing stores, existing tasks get a random UUID
+    "ALTER TABLE tasks ADD COLUMN uuid TEXT",
+    "UPDATE tasks SET uuid = lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || \
+    substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || \
+    substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))",
+    "CREATE UNIQUE INDEX tasks_uuid ON tasks (uuid)",
+    "ALTER TABLE tasks ADD COLUMN modified_at INTEGER NOT NULL DEFAULT 0",
+    "UPDATE tasks SET modified_at = COALESCE(end_time, start_time)",
+    // Status lookups are covered by tasks_status_start, these serve the other filters
+    "CREATE INDEX tasks_start ON tasks (start_time)",
+    "CREATE INDEX tasks_name ON tasks (name, status)",
+    "CREATE INDEX tasks_category ON tasks (category, start_time)",
+    // Number of the invoice a task was billed on. AUTOINCREMENT never reuses a number, even
+    // after the latest invoice is deleted.
+    "ALTER TABLE tasks ADD COLUMN invoice INTEGER",
+    "CREATE TABLE invoices (\
+    number INTEGER PRIMARY KEY AUTOINCREMENT, \
+    client TEXT NOT NULL, \
+    created_at INTEGER NOT NULL\
+    )",
+    // The latest row is the current lock, earlier ones show how it moved
+    "CREATE TABLE locks (locked_until INTEGER NOT NULL)",
+    "CREATE TABLE forced_changes (\
+    task_id INTEGER, \
+    command TEXT NOT NULL, \
+    changed_at INTEGER NOT NULL\
+    )",
+    // Every insert and update of a task, with both versions of it as JSON. The triggers keep
+    // anyone from rewriting the history after the fact.
+    "CREATE TABLE history (\
+    id INTEGER PRIMARY KEY NOT NULL, \
+    task_id INTEGER NOT NULL, \
+    command TEXT NOT NULL, \
+    user TEXT NOT NULL, \
+    changed_at INTEGER NOT NULL, \
+    old TEXT, \
+    new TEXT NOT NULL\
+    )",
+    "CREATE INDEX history_task ON history (task_id)",
+    "CREATE TRIGGER history_no_update BEFORE UPDATE ON history \
+    BEGIN SELECT RAISE(ABORT, 'the history is append-only'); END",
+    "CREATE TRIGGER history_no_delete BEFORE DELETE ON history \
+    BEGIN SELECT RAISE(ABORT, 'the history is append-only'); END",
+    // The status a task had before a change when it was not a known one
+    "ALTER TABLE history ADD COLUMN old_status TEXT",
+];
+
+// How long to wait for another process to finish writing before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Columns read by task_from_row, in order
+const COLUMNS: &str = "id, name, start_time, end_time, total_time, category, status, tags, \
+    utc_offset, uuid, modified_at, invoice";
+
+impl FromSql for TaskStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+impl ToSql for TaskStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+/// Stores tasks in the tasks table of a SQLite database.
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it and the tasks table if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Opens an existing database without creating or migrating anything.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        add_regexp_function(&connection)?;
+        Ok(Self { connection })
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    pub fn new(connection: Connection) -> Result<Self> {
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        add_regexp_function(&connection)?;
+        // Readers never block the writer and the writer never blocks readers. In-memory
+        // databases keep their own journal mode.
+        connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        let mut store = Self { connection };
+        // Only created if it does not yet exist. Two processes opening a new database at
+        // once must not both run the migrations.
+        store.begin()?;
+        match create_task_table(&store.connection) {
+            Ok(()) => store.commit()?,
+            Err(e) => {
+                let _ = store.rollback();
+                return Err(e);
+            }
+        }
+        Ok(store)
+    }
+}
+
+impl TaskStore for SqliteStore {
+    fn insert(&mut self, task: Task) -> Result<Task> {
+        let task = with_uuid(task);
+        self.connection.execute(
+            "INSERT INTO tasks (name, start_time, end_time, total_time, category, status, tags, \
+            utc_offset, uuid, modified_at, invoice) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                task.name,
+                task.start_time,
+                task.end_time,
+                task.total_time,
+                task.category,
+                task.status,
+                join_tags(&task.tags),
+                task.utc_offset,
+                task.uuid,
+                task.modified_at,
+                task.invoice
+            ],
+        )?;
+        Ok(Task {
+            id: self.connection.last_insert_rowid(),
+            ..task
+        })
+    }
+
+    fn update(&mut self, task: &Task) -> Result<()> {
+        let updated = self.connection.execute(
+            "UPDATE tasks SET name = ?2, start_time = ?3, end_time = ?4, total_time = ?5, \
+            category = ?6, status = ?7, tags = ?8, utc_offset = ?9, uuid = ?10, \
+            modified_at = ?11, invoice = ?12 WHERE id = ?1",
+            params![
+                task.id,
+                task.name,
+                task.start_time,
+                task.end_time,
+                task.total_time,
+                task.category,
+                task.status,
+                join_tags(&task.tags),
+                task.utc_offset,
+                task.uuid,
+                task.modified_at,
+                task.invoice
+            ],
+        )?;
+        if updated == 0 {
+            return Err(MetronomeError::NotFound(format!(
+                "there is no task with ID {}",
+                task.id
+            )));
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: i64) -> Result<Option<Task>> {
+        let task = self
+            .connection
+            .query_row(
+                &format!("SELECT {} FROM tasks WHERE id = ?1", COLUMNS),
+                params![id],
+                task_from_row,
+            )
+            .optional()?;
+        Ok(task)
+    }
+
+    fn query(&self, query: &TaskQuery) -> Result<Vec<Task>> {
+        let direction = if query.order.descending {
+            "DESC"
+        } else {
+            "ASC"
+        };
+        // Matches TaskOrder::compare, so every backend returns the same order
+        let order = match query.order.key {
+            SortKey::Id => format!("id {}", direction),
+            SortKey::Start => format!("start_time {0}, id {0}", direction),
+            SortKey::End => format!("end_time IS NULL, end_time {0}, id {0}", direction),
+            SortKey::Duration => format!("total_time IS NULL, total_time {0}, id {0}", direction),
+            SortKey::Name => format!("name {0}, id {0}", direction),
+            SortKey::Category => format!("category {0}, id {0}", direction),
+        };
+        let (conditions, mut values) = where_clause(query);
+        // LIMIT -1 is no limit in SQLite
+        values.push(Value::Integer(query.limit.map_or(-1, |limit| limit as i64)));
+        values.push(Value::Integer(query.offset as i64));
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT {} FROM tasks WHERE {} ORDER BY {} LIMIT ? OFFSET ?",
+            COLUMNS, conditions, order
+        ))?;
+        let tasks = stmt
+            .query_map(params_from_iter(values), task_from_row)?
+            .collect::<rusqlite::Result<Vec<Task>>>()?;
+        Ok(tasks)
+    }
+
+    fn totals(&self, query: &TaskQuery) -> Result<Vec<CategoryTotal>> {
+        let (conditions, values) = where_clause(query);
+        // The unary + keeps SQLite from scanning all of tasks_category just to group by it,
+        // so a time range is looked up in tasks_start instead
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT category, SUM(total_time) FROM tasks WHERE {} \
+            GROUP BY +category ORDER BY SUM(total_time) DESC",
+            conditions
+        ))?;
+        let totals = stmt
+            .query_map(params_from_iter(values), |row| {
+                // Categories with only active tasks have no total yet
+                let time = TaskTime::from(row.get::<_, Option<i64>>(1)?.unwrap_or(0));
+                Ok(CategoryTotal {
+                    category: row.get(0)?,
+                    time,
+                    rounded: time,
+                    billed: None,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<CategoryTotal>>>()?;
+        Ok(totals)
+    }
+
+    /// Uses a single indexed query so it is cheap enough to run on every shell prompt.
+    fn latest_active(&self) -> Result<Option<(Task, usize)>> {
+        let latest = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT {}, COUNT(*) OVER () FROM tasks \
+                    WHERE status = 'Active' ORDER BY start_time DESC, id DESC LIMIT 1",
+                    COLUMNS
+                ),
+                (),
+                |row| Ok((task_from_row(row)?, row.get(12)?)),
+            )
+            .optional()?;
+        Ok(latest)
+    }
+
+    /// Numbers come from the invoices table, which also records who each invoice was for.
+    /// Tasks synced from another store can carry higher numbers than it has issued, so the
+    /// count goes on from those too.
+    fn next_invoice_number(&mut self, client: &str, created_at: i64) -> Result<i64> {
+        self.connection.execute(
+            "INSERT INTO invoices (number, client, created_at) VALUES (\
+            1 + MAX(\
+            COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'invoices'), 0), \
+            COALESCE((SELECT MAX(invoice) FROM tasks), 0)\
+            ), ?1, ?2)",
+            params![client, created_at],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    fn locked_until(&self) -> Result<Option<i64>> {
+        let until = self
+            .connection
+            .query_row(
+                "SELECT locked_until FROM locks ORDER BY rowid DESC LIMIT 1",
+                (),
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(until)
+    }
+
+    fn lock(&mut self, until: i64) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO locks (locked_until) VALUES (?1)",
+            params![until],
+        )?;
+        Ok(())
+    }
+
+    fn log_forced_change(&mut self, change: &ForcedChange) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO forced_changes (task_id, command, changed_at) VALUES (?1, ?2, ?3)",
+            params![change.task_id, change.command, change.changed_at],
+        )?;
+        Ok(())
+    }
+
+    fn forced_changes(&self) -> Result<Vec<ForcedChange>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT task_id, command, changed_at FROM forced_changes ORDER BY rowid")?;
+        let changes = stmt
+            .query_map((), |row| {
+                Ok(ForcedChange {
+                    task_id: row.get(0)?,
+                    command: row.get(1)?,
+                    changed_at: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<ForcedChange>>>()?;
+        Ok(changes)
+    }
+
+    fn log_change(&mut self, change: &Change) -> Result<()> {
+        let json = |task: &Task| {
+            serde_json::to_string(task).map_err(|e| MetronomeError::Storage(e.to_string()))
+        };
+        self.connection.execute(
+            "INSERT INTO history (task_id, command, user, changed_at, old, old_status, new) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                change.task_id,
+                change.command,
+                change.user,
+                change.changed_at,
+                change.old.as_ref().map(json).transpose()?,
+                change.old_status,
+                json(&change.new)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn history(&self, task_id: i64) -> Result<Vec<Change>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT task_id, command, user, changed_at, old, old_status, new FROM history \
+            WHERE task_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt
+            .query_map(params![task_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, String>(6)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let task = |json: &str| {
+            serde_json::from_str::<Task>(json).map_err(|e| MetronomeError::Storage(e.to_string()))
+        };
+        rows.into_iter()
+            .map(
+                |(task_id, command, user, changed_at, old, old_status, new)| {
+                    Ok(Change {
+                        task_id,
+                        command,
+                        user,
+                        changed_at,
+                        old: old.as_deref().map(task).transpose()?,
+                        old_status,
+                        new: task(&new)?,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    fn unknown_statuses(&self) -> Result<Vec<UnknownStatus>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, name, start_time, status, end_time FROM tasks \
+            WHERE status NOT IN ('Active', 'Complete') ORDER BY id",
+        )?;
+        let unknown = stmt
+            .query_map([], |row| {
+                Ok(UnknownStatus {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    start_time: row.get(2)?,
+                    status: row.get(3)?,
+                    end_time: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(unknown)
+    }
+
+    // The task may not be readable before, so only the status is written
+    fn set_status(&mut self, id: i64, status: TaskStatus) -> Result<()> {
+        let updated = self.connection.execute(
+            "UPDATE tasks SET status = ?2 WHERE id = ?1",
+            params![id, status],
+        )?;
+        if updated == 0 {
+            return Err(MetronomeError::NotFound(format!(
+                "there is no task with ID {}",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Takes the write lock straight away, so a transaction that reads and then writes
+    /// cannot be invalidated by another process writing in between.
+    fn begin(&mut self) -> Result<()> {
+        self.connection.execute_batch("BEGIN IMMEDIATE")?;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.connection.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.connection.execute_batch("ROLLBACK")?;
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) -> Result<()> {
+        self.connection.close().map_err(|(_, e)| e.into())
+    }
+}
+
+/// Conditions matching `query` and the values to bind to them. Only the fields that are set
+/// become conditions, so SQLite can pick an index for them.
+fn where_clause(query: &TaskQuery) -> (String, Vec<Value>) {
+    let mut conditions: Vec<String> = vec![];
+    let mut values = vec![];
+    // A comma separated placeholder for each value, e.g. "?, ?, ?"
+    let placeholders = |count: usize| vec!["?"; count].join(", ");
+
+    if let Some(status) = query.status {
+        conditions.push(String::from("status = ?"));
+        values.push(Value::Text(status.to_string()));
+    }
+    if let Some(name) = &query.name {
+        conditions.push(String::from("name = ?"));
+        values.push(Value::Text(name.clone()));
+    }
+    if let Some(pattern) = &query.name_pattern {
+        conditions.push(String::from(if pattern.is_glob() {
+            "name GLOB ?"
+        } else {
+            "name REGEXP ?"
+        }));
+        values.push(Value::Text(String::from(pattern.as_str())));
+    }
+    if !query.categories.is_empty() {
+        conditions.push(format!(
+            "category IN ({})",
+            placeholders(query.categories.len())
+        ));
+        values.extend(query.categories.iter().cloned().map(Value::Text));
+    }
+    if !query.excluded_categories.is_empty() {
+        conditions.push(format!(
+            "category NOT IN ({})",
+            placeholders(query.excluded_categories.len())
+        ));
+        values.extend(query.excluded_categories.iter().cloned().map(Value::Text));
+    }
+    if let Some(started_after) = query.started_after {
+        conditions.push(String::from("start_time > ?"));
+        values.push(Value::Integer(started_after));
+    }
+    if let Some(started_before) = query.started_before {
+        conditions.push(String::from("start_time < ?"));
+        values.push(Value::Integer(started_before));
+    }
+
+    // Active tasks have no total time, so they only match when measured up to now
+    for (operator, bound) in [(">=", query.min_duration), ("<=", query.max_duration)] {
+        let Some(bound) = bound else {
+            continue;
+        };
+        match query.now {
+            Some(now) => {
+                conditions.push(format!(
+                    "COALESCE(total_time, ? - start_time) {} ?",
+                    operator
+                ));
+                values.push(Value::Integer(now));
+            }
+            None => conditions.push(format!("total_time {} ?", operator)),
+        }
+        values.push(Value::Integer(bound));
+    }
+
+    if conditions.is_empty() {
+        (String::from("1"), values)
+    } else {
+        (conditions.join(" AND "), values)
+    }
+}
+
+// SQLite has the REGEXP operator, but leaves it to the application to define
+fn add_regexp_function(connection: &Connection) -> rusqlite::Result<()> {
+    connection.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            // Compiled once per statement rather than once per row
+            let regex: Arc<Regex> = ctx.get_or_create_aux(0, |pattern| {
+                Regex::new(pattern.as_str()?)
+                    .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))
+            })?;
+            let text = ctx.get::<String>(1)?;
+            Ok(regex.is_match(&text))
+        },
+    )
+}
+
+fn create_task_table(connection: &Connection) -> Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS tasks (\
+        id INTEGER PRIMARY KEY NOT NULL, \
+        name TEXT NOT NULL, \
+        start_time INTEGER NOT NULL, \
+        end_time INTEGER, \
+        total_time INTEGER, \
+        category TEXT NOT NULL, \
+        status TEXT NOT NULL\
+        )",
+        (),
+    )?;
+    // Lets status lookups (e.g. the prompt on every shell render) avoid a full scan
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS tasks_status_start ON tasks (status, start_time)",
+        (),
+    )?;
+    migrate(connection)?;
+    Ok(())
+}
+
+fn migrate(connection: &Connection) -> rusqlite::Result<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", (), |row| row.get(0))?;
+    for migration in MIGRATIONS.iter().skip(version) {
+        connection.execute(migration, ())?;
+    }
+    connection.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    Ok(())
+}
+
+fn task_from_row(row: &Row) -> rusqlite::Result<Task> {
+    let tags: Option<String> = row.get(7)?;
+    Ok(Task {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        start_time: row.get(2)?,
+        end_time: row.get(3)?,
+        total_time: row.get(4)?,
+        category: row.get(5)?,
+        status: row.get(6)?,
+        tags: tags
+            .map(|tags| tags.split(',').map(String::from).collect())
+            .unwrap_or_default(),
+        utc_offset: row.get(8)?,
+        uuid: row.get(9)?,
+        modified_at: row.get(10)?,
+        invoice: row.get(11)?,
+    })
+}
+
+// Tags are stored as a comma separated list, NULL when there are none
+fn join_tags(tags: &[String]) -> Option<String> {
+    (!tags.is_empty()).then(|| tags.join(","))
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use super::error::Result;
+use super::store::{TaskQuery, TaskStore};
+use super::task::Task;
+
+/// A task that was changed differently in the two stores. The most recently modified
+/// version is kept in both, the local one when they were modified at the same time.
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub local: Task,
+    pub remote: Task,
+}
+
+impl Conflict {
+    pub fn kept_remote(&self) -> bool {
+        self.remote.modified_at > self.local.modified_at
+    }
+
+    pub fn kept(&self) -> &Task {
+        if self.kept_remote() {
+            &self.remote
+        } else {
+            &self.local
+        }
+    }
+}
+
+/// What a sync changed in each store.
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncReport {
+    pub pulled: Vec<Task>, // Tasks copied from the remote store
+    pub pushed: Vec<Task>, // Tasks copied to the remote store
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Makes both stores hold the same tasks, matching them by UUID. IDs are local to a store,
+/// so copied tasks get a new ID.
+pub(crate) fn merge(local: &mut dyn TaskStore, remote: &mut dyn TaskStore) -> Result<SyncReport> {
+    let local_tasks = local.query(&TaskQuery::default())?;
+    let mut remote_tasks: HashMap<String, Task> = remote
+        .query(&TaskQuery::default())?
+        .into_iter()
+        .map(|task| (task.uuid.clone(), task))
+        .collect();
+    let mut report = SyncReport::default();
+
+    for local_task in local_tasks {
+        match remote_tasks.remove(&local_task.uuid) {
+            None => report.pushed.push(remote.insert(local_task)?),
+            Some(remote_task) if same_task(&local_task, &remote_task) => {}
+            Some(remote_task) => {
+                let conflict = Conflict {
+                    local: local_task,
+                    remote: remote_task,
+                };
+                if conflict.kept_remote() {
+                    local.update(&Task {
+                        id: conflict.local.id,
+                        ..conflict.remote.clone()
+                    })?;
+                } else {
+                    remote.update(&Task {
+                        id: conflict.remote.id,
+                        ..conflict.local.clone()
+                    })?;
+                }
+                report.conflicts.push(conflict);
+            }
+        }
+    }
+
+    // Whatever is left only exists remotely. Insert in ID order to keep the remote order.
+    let mut remote_only: Vec<Task> = remote_tasks.into_values().collect();
+    remote_only.sort_by_key(|task| task.id);
+    for task in remote_only {
+        report.pulled.push(local.insert(task)?);
+    }
+
+    Ok(report)
+}
+
+// Equal apart from the store specific ID and the modification time
+fn same_task(a: &Task, b: &Task) -> bool {
+    Task {
+        id: b.id,
+        modified_at: b.modified_at,
+        ..a.clone()
+    } == *b
+}
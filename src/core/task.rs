@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, Serialize, Deserialize)]
+pub enum TaskStatus {
+    Active,
+    Complete,
+}
+
+/// A tracked task, as kept by a `TaskStore`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Task {
+    pub id: i64,
+    pub name: String,
+    pub start_time: i64,         // Unix timestamp
+    pub end_time: Option<i64>,   // Unix timestamp, None while the task is active
+    pub total_time: Option<i64>, // Seconds, None while the task is active
+    pub category: String,
+    pub status: TaskStatus,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub utc_offset: Option<i32>, // Seconds east of UTC the task was started in
+    #[serde(default)]
+    pub uuid: String, // Identifies the task across stores, assigned by the store when empty
+    #[serde(default)]
+    pub modified_at: i64, // Unix timestamp of the last change, used to resolve sync conflicts
+    #[serde(default)]
+    pub invoice: Option<i64>, // Number of the invoice the task was billed on
+}
+
+impl Task {
+    /// Seconds spent on the task, counting an active task up to `now`.
+    pub fn elapsed(&self, now: i64) -> i64 {
+        self.total_time.unwrap_or(now - self.start_time)
+    }
+}
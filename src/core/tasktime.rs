@@ -1,38 +1,268 @@
-use std::fmt::Display;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub};
+use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+use strum_macros::{Display, EnumString};
+
+/// How durations are written in tables and messages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum DurationStyle {
+    /// Every unit, e.g. "1h 45m 0s".
+    #[default]
+    Full,
+    /// Leading zero units and seconds left out, e.g. "1h45m", as in `TaskTime::short`.
+    Compact,
+    /// Hours, minutes and seconds as on a clock, e.g. "01:45:00".
+    Clock,
+    /// Hours to two decimal places, e.g. "1.75h", as invoices usually want them.
+    Decimal,
+    /// Whole days split off long durations, e.g. "2d 3h 15m". Shorter ones are written in full.
+    Days,
+}
+
+/// A duration in whole seconds, such as the total time of a task.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TaskTime {
-    // Time data for the task for recording duration in H:M:S form
-    hours: i64,
-    minutes: i64,
+    // Negative only as the result of subtracting a longer time
     seconds: i64,
-    // Number of total seconds for the task
-    raw: i64,
 }
 
 impl From<i64> for TaskTime {
     fn from(total_seconds: i64) -> Self {
-        let hours: i64 = total_seconds / 3600;
-        let mut remainder: i64 = total_seconds % 3600;
-        let minutes: i64 = remainder / 60;
-        remainder = remainder % 60;
-        let seconds = remainder;
-
         Self {
-            hours,
-            minutes,
-            seconds,
-            raw: total_seconds,
+            seconds: total_seconds,
+        }
+    }
+}
+
+impl TaskTime {
+    pub const ZERO: TaskTime = TaskTime { seconds: 0 };
+
+    pub fn total_seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    /// The duration in hours, e.g. 1.75 for 1h 45m.
+    pub fn hours(&self) -> f64 {
+        self.seconds as f64 / 3600.0
+    }
+
+    pub fn format(&self, style: DurationStyle) -> String {
+        let sign = if self.seconds < 0 { "-" } else { "" };
+        let (hours, minutes, seconds) = self.units();
+        match style {
+            DurationStyle::Full => self.to_string(),
+            DurationStyle::Compact => self.short(),
+            DurationStyle::Clock => format!("{}{:02}:{:02}:{:02}", sign, hours, minutes, seconds),
+            DurationStyle::Decimal => format!("{:.2}h", self.hours()),
+            DurationStyle::Days if hours >= 24 => {
+                format!("{}{}d {}h {}m", sign, hours / 24, hours % 24, minutes)
+            }
+            DurationStyle::Days => self.to_string(),
+        }
+    }
+
+    /// Compact form that omits leading zero units, e.g. "1h5m" or "42s".
+    pub fn short(&self) -> String {
+        let sign = if self.seconds < 0 { "-" } else { "" };
+        let (hours, minutes, seconds) = self.units();
+        if hours > 0 {
+            format!("{}{}h{}m", sign, hours, minutes)
+        } else if minutes > 0 {
+            format!("{}{}m", sign, minutes)
+        } else {
+            format!("{}{}s", sign, seconds)
+        }
+    }
+
+    // Hours, minutes and seconds of the absolute duration
+    fn units(&self) -> (i64, i64, i64) {
+        let seconds = self.seconds.unsigned_abs();
+        let units = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+        (units.0 as i64, units.1 as i64, units.2 as i64)
+    }
+}
+
+impl fmt::Display for TaskTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.seconds < 0 { "-" } else { "" };
+        let (hours, minutes, seconds) = self.units();
+        write!(f, "{}{}h {}m {}s", sign, hours, minutes, seconds)
+    }
+}
+
+/// Parses durations written with units, e.g. "1h30m", "90m", "1.5h" or "2d 4h", or as on a
+/// clock, e.g. "1:30" or "1:30:15".
+impl FromStr for TaskTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid duration \"{}\", expected e.g. 1h30m, 90m, 1.5h or 1:30",
+                s
+            )
+        };
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(invalid());
+        }
+        if s.contains(':') {
+            return parse_clock(s).map(Self::from).ok_or_else(invalid);
+        }
+
+        let mut total_seconds = 0.0;
+        let mut number = String::new();
+        for c in s.chars() {
+            if c.is_ascii_digit() || c == '.' {
+                number.push(c);
+                continue;
+            }
+            // Units may be separated by spaces, as in the full style
+            if c == ' ' && number.is_empty() {
+                continue;
+            }
+            let unit = match c {
+                'd' => 86400.0,
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                _ => return Err(invalid()),
+            };
+            let value: f64 = number.parse().map_err(|_| invalid())?;
+            total_seconds += value * unit;
+            number.clear();
+        }
+        // Every number needs a unit, and the total has to fit in whole seconds
+        if !number.is_empty() || !total_seconds.is_finite() || total_seconds >= i64::MAX as f64 {
+            return Err(invalid());
         }
+        Ok(Self::from(total_seconds.round() as i64))
     }
 }
 
-impl Display for TaskTime {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            format!("{}h {}m {}s", self.hours, self.minutes, self.seconds)
-        )
+// Hours and minutes, and optionally seconds, e.g. "1:30" or "01:30:15". None if the numbers are
+// invalid or the duration is too long to count in seconds.
+fn parse_clock(s: &str) -> Option<i64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 || parts.iter().any(|part| part.is_empty()) {
+        return None;
+    }
+    let numbers: Vec<i64> = parts
+        .iter()
+        .map(|part| part.parse().ok().filter(|n: &i64| *n >= 0))
+        .collect::<Option<_>>()?;
+    if numbers[1..].iter().any(|&n| n >= 60) {
+        return None;
+    }
+    numbers[0]
+        .checked_mul(3600)?
+        .checked_add(numbers[1] * 60)?
+        .checked_add(numbers.get(2).copied().unwrap_or(0))
+}
+
+impl Add for TaskTime {
+    type Output = TaskTime;
+
+    fn add(self, other: TaskTime) -> TaskTime {
+        // Saturates rather than overflowing on durations no one tracks
+        TaskTime::from(self.seconds.saturating_add(other.seconds))
+    }
+}
+
+impl AddAssign for TaskTime {
+    fn add_assign(&mut self, other: TaskTime) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for TaskTime {
+    type Output = TaskTime;
+
+    fn sub(self, other: TaskTime) -> TaskTime {
+        TaskTime::from(self.seconds.saturating_sub(other.seconds))
+    }
+}
+
+impl Sum for TaskTime {
+    fn sum<I: Iterator<Item = TaskTime>>(iter: I) -> TaskTime {
+        iter.fold(TaskTime::default(), Add::add)
+    }
+}
+
+impl<'a> Sum<&'a TaskTime> for TaskTime {
+    fn sum<I: Iterator<Item = &'a TaskTime>>(iter: I) -> TaskTime {
+        iter.copied().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_time() {
+        let parse = |duration: &str| {
+            duration
+                .parse::<TaskTime>()
+                .map(|time| time.total_seconds())
+        };
+        for duration in [
+            "1h30m",
+            "90m",
+            "5400s",
+            "1.5h",
+            "1h 30m 0s",
+            "1:30",
+            "01:30:00",
+        ] {
+            assert_eq!(Ok(5400), parse(duration), "{}", duration);
+        }
+        assert_eq!(Ok(2 * 86400 + 4 * 3600), parse("2d 4h"));
+        for duration in [
+            "",
+            "1x",
+            "90",
+            "h",
+            "1:60",
+            "1:2:3:4",
+            "-1h",
+            "1.2.3h",
+            "9999999999999999:00",
+            "9999999999999999999999h",
+        ] {
+            assert!(parse(duration).is_err(), "{}", duration);
+        }
+
+        let time = TaskTime::from(6300);
+        assert_eq!("1h 45m 0s", time.format(DurationStyle::Full));
+        assert_eq!("1h45m", time.format(DurationStyle::Compact));
+        assert_eq!("01:45:00", time.format(DurationStyle::Clock));
+        assert_eq!("1.75h", time.format(DurationStyle::Decimal));
+        assert_eq!("1h 45m 0s", time.format(DurationStyle::Days));
+        let long = TaskTime::from(2 * 86400 + 3 * 3600 + 15 * 60 + 10);
+        assert_eq!("2d 3h 15m", long.format(DurationStyle::Days));
+        assert_eq!("51:15:10", long.format(DurationStyle::Clock));
+
+        // Formatting and parsing round-trips in every style that keeps the seconds
+        for style in [DurationStyle::Full, DurationStyle::Clock] {
+            assert_eq!(Ok(long), long.format(style).parse(), "{}", style);
+        }
+
+        let times = [TaskTime::from(600), TaskTime::from(3000)];
+        assert_eq!(TaskTime::from(3600), times[0] + times[1]);
+        assert_eq!(TaskTime::from(3600), times.iter().sum());
+        assert_eq!(TaskTime::from(-2400), times[0] - times[1]);
+        assert_eq!("-0h 40m 0s", (times[0] - times[1]).to_string());
+
+        // Sums too long to count in seconds saturate instead of overflowing
+        let max = TaskTime::from(i64::MAX);
+        assert_eq!(max, max + times[0]);
+        assert_eq!(max, [max, max].iter().sum());
+        let min = TaskTime::from(0) - max - max;
+        assert_eq!(i64::MIN, min.total_seconds());
+        assert!(min.to_string().starts_with("-2562047788015215h"));
     }
 }
@@ -0,0 +1,154 @@
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta,
+    TimeZone, Weekday,
+};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+/// Time zone that timestamps are displayed in.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DisplayZone {
+    /// The zone of the machine running metronome.
+    #[default]
+    Local,
+    /// Each task is shown in the UTC offset it was started in. Days and weeks are not tied to
+    /// a single task, so they start in the local zone.
+    Recorded,
+    Iana(Tz),
+    Fixed(FixedOffset),
+}
+
+impl FromStr for DisplayZone {
+    type Err = String;
+
+    /// Accepts "local", "recorded", an IANA name such as "Europe/Berlin" or an offset such as "+02:00".
+    fn from_str(zone: &str) -> Result<Self, Self::Err> {
+        match zone.to_lowercase().as_str() {
+            "local" => return Ok(DisplayZone::Local),
+            "recorded" => return Ok(DisplayZone::Recorded),
+            _ => {}
+        }
+        if let Ok(tz) = zone.parse::<Tz>() {
+            return Ok(DisplayZone::Iana(tz));
+        }
+        zone.parse::<FixedOffset>()
+            .map(DisplayZone::Fixed)
+            .map_err(|_| format!("unknown time zone \"{}\"", zone))
+    }
+}
+
+impl fmt::Display for DisplayZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayZone::Local => write!(f, "local"),
+            DisplayZone::Recorded => write!(f, "recorded"),
+            DisplayZone::Iana(tz) => write!(f, "{}", tz.name()),
+            DisplayZone::Fixed(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+impl DisplayZone {
+    /// Formats a unix timestamp. `recorded_offset` is the UTC offset in seconds the task was
+    /// started in, and is only used by `DisplayZone::Recorded`.
+    pub fn format(&self, timestamp: i64, recorded_offset: Option<i32>, fmt: &str) -> String {
+        let Some(utc) = DateTime::from_timestamp(timestamp, 0) else {
+            return String::from("INVALID");
+        };
+        match self {
+            DisplayZone::Local => utc.with_timezone(&Local).format(fmt).to_string(),
+            DisplayZone::Recorded => match recorded_offset.and_then(FixedOffset::east_opt) {
+                Some(offset) => utc.with_timezone(&offset).format(fmt).to_string(),
+                None => utc.with_timezone(&Local).format(fmt).to_string(),
+            },
+            DisplayZone::Iana(tz) => utc.with_timezone(tz).format(fmt).to_string(),
+            DisplayZone::Fixed(offset) => utc.with_timezone(offset).format(fmt).to_string(),
+        }
+    }
+
+    /// Unix timestamp of the most recent midnight at or before `timestamp` in this zone.
+    pub fn start_of_day(&self, timestamp: i64) -> i64 {
+        match self.date(timestamp) {
+            Some(date) => self.midnight(date),
+            None => timestamp,
+        }
+    }
+
+    /// The calendar date at `timestamp` in this zone. Recorded offsets count as local time.
+    pub fn date(&self, timestamp: i64) -> Option<NaiveDate> {
+        let utc = DateTime::from_timestamp(timestamp, 0)?;
+        Some(match self {
+            DisplayZone::Local | DisplayZone::Recorded => utc.with_timezone(&Local).date_naive(),
+            DisplayZone::Iana(tz) => utc.with_timezone(tz).date_naive(),
+            DisplayZone::Fixed(offset) => utc.with_timezone(offset).date_naive(),
+        })
+    }
+
+    /// Unix timestamp of the start of `date` in this zone.
+    pub fn midnight(&self, date: NaiveDate) -> i64 {
+        match self {
+            DisplayZone::Local | DisplayZone::Recorded => midnight_in(&Local, date),
+            DisplayZone::Iana(tz) => midnight_in(tz, date),
+            DisplayZone::Fixed(offset) => midnight_in(offset, date),
+        }
+    }
+
+    /// Unix timestamp of `time` on `date` in this zone. A time skipped by a DST change is
+    /// taken an hour later.
+    pub fn at(&self, date: NaiveDate, time: NaiveTime) -> i64 {
+        let datetime = date.and_time(time);
+        match self {
+            DisplayZone::Local | DisplayZone::Recorded => at_in(&Local, datetime),
+            DisplayZone::Iana(tz) => at_in(tz, datetime),
+            DisplayZone::Fixed(offset) => at_in(offset, datetime),
+        }
+    }
+
+    /// Unix timestamp of midnight on the most recent `week_start` at or before `timestamp`.
+    pub fn start_of_week(&self, timestamp: i64, week_start: Weekday) -> i64 {
+        let days = match self {
+            DisplayZone::Local | DisplayZone::Recorded => {
+                days_into_week(&Local, timestamp, week_start)
+            }
+            DisplayZone::Iana(tz) => days_into_week(tz, timestamp, week_start),
+            DisplayZone::Fixed(offset) => days_into_week(offset, timestamp, week_start),
+        };
+        // Stepping back whole days from noon keeps clear of DST changes around midnight
+        let midday = self.start_of_day(timestamp) + 12 * 3600;
+        self.start_of_day(midday - days * 86400)
+    }
+}
+
+/// UTC offset in seconds of the local zone at `timestamp`, recorded with each new task.
+pub fn local_offset(timestamp: i64) -> i32 {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|utc| utc.with_timezone(&Local).offset().local_minus_utc())
+        .unwrap_or(0)
+}
+
+fn days_into_week<Z: TimeZone>(zone: &Z, timestamp: i64, week_start: Weekday) -> i64 {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|utc| {
+            let weekday = utc.with_timezone(zone).weekday();
+            (weekday.num_days_from_monday() + 7 - week_start.num_days_from_monday()) as i64 % 7
+        })
+        .unwrap_or(0)
+}
+
+fn at_in<Z: TimeZone>(zone: &Z, datetime: NaiveDateTime) -> i64 {
+    [datetime, datetime + TimeDelta::hours(1)]
+        .iter()
+        .find_map(|datetime| zone.from_local_datetime(datetime).earliest())
+        .map(|dt| dt.timestamp())
+        .unwrap_or_else(|| datetime.and_utc().timestamp())
+}
+
+fn midnight_in<Z: TimeZone>(zone: &Z, date: NaiveDate) -> i64 {
+    // A DST change can skip midnight, in which case the day starts at the first valid time
+    (0..24)
+        .filter_map(|hour| date.and_hms_opt(hour, 0, 0))
+        .find_map(|time| zone.from_local_datetime(&time).earliest())
+        .map(|dt| dt.timestamp())
+        .unwrap_or_else(|| date.and_time(NaiveTime::MIN).and_utc().timestamp())
+}
@@ -0,0 +1,26 @@
+//! Time tracking for the command line.
+//!
+//! The [`Tracker`] records tasks in a [`store::TaskStore`], by default a SQLite database, and
+//! returns plain data, leaving presentation to the caller. The `metronome` binary is a thin layer on top of it.
+//!
+//! ```no_run
+//! use metronome::{Config, Tracker};
+//!
+//! let mut tracker = Tracker::open("tasks.db", Config::default())?;
+//! let started = tracker.start("Write report", Some("Work"), &[])?;
+//! println!("Started task {}", started.task.id);
+//! # Ok::<(), metronome::error::MetronomeError>(())
+//! ```
+
+mod core;
+
+pub use crate::core::{
+    billing, clock, config, doctor, error, filters, gaps, invoice, prompt, store, sync, task,
+    tasktime, timezone,
+};
+pub use crate::core::{
+    CategoryTotal, Goal, Goals, ListMode, ListOptions, Started, Status, Totals, Tracker, DB_NAME,
+};
+pub use config::Config;
+pub use error::{MetronomeError, Result};
+pub use task::{Task, TaskStatus};
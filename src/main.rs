@@ -1,53 +1,348 @@
 mod cli;
-mod core;
+mod output;
 
-use core::filters::Filter;
-use rusqlite::Connection;
+use chrono::NaiveDate;
+use clap::ArgMatches;
+use metronome::clock::{Clock, FixedClock, SystemClock};
+use metronome::config::{self, OutputFormat};
+use metronome::error::{self, MetronomeError, Result};
+use metronome::filters::Filter;
+use metronome::gaps::Gap;
+use metronome::invoice::InvoiceFormat;
+use metronome::prompt;
+use metronome::store::{self, NamePattern, SortKey};
+use metronome::tasktime::TaskTime;
+use metronome::timezone::DisplayZone;
+use metronome::{Config, ListMode, ListOptions, Tracker};
+use output::Column;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
 
-fn main() -> rusqlite::Result<()> {
-    let conn = Connection::open(core::DB_NAME)?;
-    core::create_task_table(&conn)?; // Only created if it does not yet exist
+fn main() {
+    let exit_code = match run() {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            e.exit_code()
+        }
+    };
+    process::exit(exit_code);
+}
 
+// Returns the exit code for a successful command
+fn run() -> Result<i32> {
     let matches = cli::match_cli();
 
+    let mut config = Config::load();
+    if let Some(timezone) = matches.get_one::<DisplayZone>("tz") {
+        config.timezone = *timezone;
+    }
+    if let Some(format) = matches.get_one::<OutputFormat>("output") {
+        config.output_format = *format;
+    }
+
+    // Settings can be changed without a store
+    if let Some(("config", sub_args)) = matches.subcommand() {
+        run_config(&config, sub_args)?;
+        return Ok(0);
+    }
+
+    let db: &String = matches.get_one("db").unwrap(); // has default value
+    let clock: Box<dyn Clock> = match matches.get_one::<i64>("now") {
+        Some(now) => Box::new(FixedClock::new(*now)),
+        None => Box::new(SystemClock),
+    };
+
+    // The prompt runs on every shell render, so it only reads and never creates the store
+    if let Some(("prompt", sub_args)) = matches.subcommand() {
+        let format: &String = sub_args.get_one("format").unwrap(); // has default value
+        let idle: &String = sub_args.get_one("idle").unwrap(); // has default value
+        let task = if Path::new(db).exists() {
+            Tracker::open_read_only(db, config.clone())?.prompt_task()?
+        } else {
+            None
+        };
+        match task {
+            Some(task) => println!(
+                "{}",
+                prompt::render(format, &task, clock.now(), &config.timezone)
+            ),
+            None if !idle.is_empty() => println!("{}", idle),
+            None => {}
+        }
+        return Ok(0);
+    }
+
+    let mut tracker = Tracker::open(db, config)?
+        .with_clock(clock)
+        .with_force(matches.get_flag("force"));
+    let exit_code = run_command(&mut tracker, &matches)?;
+    tracker.close()?;
+
+    Ok(exit_code)
+}
+
+fn run_command(tracker: &mut Tracker, matches: &ArgMatches) -> Result<i32> {
+    let config = &tracker.config().clone();
+
     match matches.subcommand() {
         Some(("start", sub_args)) => {
             let task: &String = sub_args.get_one("task").unwrap(); // required argument
-            let category = sub_args.get_one("category");
-            core::start_task(&conn, task, category)
+            let category = sub_args.get_one::<String>("category");
+            let started = tracker.start(task, category.map(String::as_str), &get_tags(sub_args))?;
+            output::print_started(config, &started);
+        }
+        Some(("switch", sub_args)) => {
+            let task: &String = sub_args.get_one("task").unwrap(); // required argument
+            let category = sub_args.get_one::<String>("category");
+            let switched =
+                tracker.switch(task, category.map(String::as_str), &get_tags(sub_args))?;
+            output::print_switched(config, &switched);
+        }
+        Some(("continue", sub_args)) => {
+            let task = sub_args.get_one::<String>("task");
+            let started = tracker.continue_task(task.map(String::as_str))?;
+            output::print_started(config, &started);
         }
         Some(("end", sub_args)) => {
             if sub_args.get_flag("all") {
-                core::end_all_active(&conn)
+                output::print_ended_all(config, &tracker.end_all()?);
             } else if sub_args.get_flag("last") {
-                core::end_last(&conn)
+                output::print_ended(config, &tracker.end_last()?);
             } else {
                 let task: &String = sub_args.get_one("task").unwrap(); // required argument
-                core::end_task(&conn, task)
+                for ended in tracker.end(task)? {
+                    output::print_ended(config, &ended);
+                }
             }
         }
         Some(("list", sub_args)) => {
-            let filter = Filter::from(sub_args.get_one("filter"));
-            if sub_args.get_flag("active") {
-                core::list_active(&conn, filter)
+            let filter = match sub_args.contains_id("filter") {
+                true => get_filter(config, sub_args),
+                false if config.list_filter != Filter::All => {
+                    output::print_filter(config, config.list_filter);
+                    config.list_filter
+                }
+                false => Filter::All,
+            };
+            let mode = if sub_args.get_flag("active") {
+                ListMode::Active
             } else if sub_args.get_flag("completed") {
-                core::list_complete(&conn, filter)
+                ListMode::Complete
+            } else if sub_args.get_flag("all") {
+                ListMode::All
             } else {
-                core::list_all(&conn, filter)
-            }
+                config.list_mode
+            };
+            let (sort, descending) = *sub_args.get_one::<(SortKey, bool)>("sort").unwrap(); // has default value
+            let (excluded, included): (Vec<&(String, bool)>, _) = sub_args
+                .get_many::<(String, bool)>("category")
+                .unwrap_or_default()
+                .partition(|(_, excluded)| *excluded);
+            let duration = |id| {
+                sub_args
+                    .get_one::<TaskTime>(id)
+                    .map(TaskTime::total_seconds)
+            };
+            let options = ListOptions {
+                sort,
+                // Reversing a descending sort makes it ascending again
+                descending: descending != sub_args.get_flag("reverse"),
+                limit: sub_args.get_one::<usize>("limit").copied(),
+                offset: *sub_args.get_one::<usize>("offset").unwrap(), // has default value
+                categories: included.into_iter().map(|(name, _)| name.clone()).collect(),
+                excluded_categories: excluded.into_iter().map(|(name, _)| name.clone()).collect(),
+                name: match sub_args.get_one::<String>("name") {
+                    Some(name) => Some(name.parse::<NamePattern>()?),
+                    None => None,
+                },
+                min_duration: duration("min"),
+                max_duration: duration("max"),
+                ..ListOptions::new(mode, filter)
+            };
+            let columns: Vec<Column> = match sub_args.get_many("columns") {
+                Some(columns) => columns.copied().collect(),
+                None => Column::DEFAULT.to_vec(),
+            };
+            let tasks = tracker.list(&options)?;
+            output::print_list_rows(config, &tasks, &columns, tracker.now());
         }
         Some(("total", sub_args)) => {
-            let filter = Filter::from(sub_args.get_one::<String>("filter"));
-            core::sum_task_times(&conn, filter, sub_args.get_one("category"))
+            let filter = get_filter(config, sub_args);
+            let category = sub_args.get_one::<String>("category");
+            let category = category.map(String::as_str);
+            let totals = match sub_args.get_flag("money") {
+                true => tracker.billed_totals(filter, category)?,
+                false => tracker.totals(filter, category)?,
+            };
+            output::print_total_time_rows(config, &totals);
         }
-        _ => {
-            // TODO automatically show help menu and exit
-            panic!("Invalid subcommand. See help menu for list of valid commands.")
+        Some(("invoice", sub_args)) => {
+            let client: &String = sub_args.get_one("client").unwrap(); // required argument
+            let since: NaiveDate = *sub_args.get_one("since").unwrap(); // required argument
+            let until = match sub_args.get_one::<NaiveDate>("until") {
+                Some(until) => *until,
+                None => config.timezone.date(tracker.now()).unwrap_or(since),
+            };
+            let format: InvoiceFormat = *sub_args.get_one("format").unwrap(); // has default value
+            let invoice = tracker.invoice(client, since, until)?;
+            output::print_invoice(&invoice, format);
         }
-    }?;
+        Some(("lock", sub_args)) => {
+            if let Some(until) = sub_args.get_one::<NaiveDate>("until") {
+                tracker.lock(*until)?;
+            }
+            output::print_lock(config, tracker.locked_until()?, &tracker.forced_changes()?);
+        }
+        Some(("gaps", sub_args)) => {
+            let day = match sub_args.get_one::<NaiveDate>("day") {
+                Some(day) => *day,
+                None => config.timezone.date(tracker.now()).unwrap_or_default(),
+            };
+            let gaps = tracker.gaps(day)?;
+            if sub_args.get_flag("fill") {
+                fill_gaps(tracker, config, &gaps)?;
+            } else {
+                output::print_gaps(config, &gaps);
+            }
+        }
+        Some(("overlaps", sub_args)) => {
+            let day = sub_args.get_one::<NaiveDate>("day").copied();
+            output::print_overlaps(config, &tracker.overlaps(day)?, tracker.now());
+        }
+        Some(("doctor", sub_args)) => {
+            let stale_after: TaskTime = *sub_args.get_one("stale-after").unwrap(); // has default value
+            let fixed = if sub_args.get_flag("fix") {
+                tracker.repair(stale_after)?
+            } else {
+                vec![]
+            };
+            let problems = tracker.check(stale_after)?;
+            output::print_problems(config, &fixed, &problems, tracker.now());
+            if !problems.is_empty() {
+                return Ok(error::EXIT_PROBLEMS);
+            }
+        }
+        Some(("log", sub_args)) => {
+            let id: i64 = *sub_args.get_one("id").unwrap(); // required argument
+            output::print_history(config, id, &tracker.history(id)?);
+        }
+        Some(("goals", _)) => output::print_goals(config, &tracker.goals()?),
+        Some(("status", _)) => {
+            let status = tracker.status()?;
+            output::print_status(config, &status);
+            if status.active.is_empty() {
+                return Ok(error::EXIT_NO_ACTIVE);
+            }
+        }
+        Some(("sync", sub_args)) => {
+            let path: &String = sub_args.get_one("store").unwrap(); // required argument
+            let db: &String = matches.get_one("db").unwrap(); // has default value
+            check_sync_path(path, db)?;
+            let mut other = store::open(path)?;
+            let report = tracker.sync(other.as_mut())?;
+            other.close()?;
+            output::print_synced(config, path, &report);
+        }
+        _ => unreachable!("clap requires one of the subcommands above"),
+    }
+
+    Ok(0)
+}
+
+fn run_config(config: &Config, sub_args: &ArgMatches) -> Result<()> {
+    match sub_args.subcommand() {
+        Some(("get", args)) => {
+            let key: &String = args.get_one("key").unwrap(); // required argument
+                                                             // Category, tag and weekday settings fall back to others when they are not set
+            let Some(value) = config.get(key) else {
+                return Err(MetronomeError::NotFound(format!("{} is not set", key)));
+            };
+            println!("{}", value);
+        }
+        Some(("set", args)) => {
+            let key: &String = args.get_one("key").unwrap(); // required argument
+            let value: &String = args.get_one("value").unwrap(); // required argument
+            let Some(path) = config::path() else {
+                return Err(MetronomeError::NotFound(String::from(
+                    "there is no config directory, set METRONOME_CONFIG to a file instead",
+                )));
+            };
+            config::write(&path, key, value)?;
+            println!("Set {} to \"{}\" in {}", key, value, path.display());
+        }
+        Some(("list", _)) => {
+            for key in config.keys() {
+                println!("{} = {}", key, config.get(&key).unwrap_or_default());
+            }
+        }
+        _ => unreachable!("clap requires one of the subcommands above"),
+    }
+    Ok(())
+}
 
-    conn.close()
-        .expect(format!("Could not close connection to {}", core::DB_NAME).as_str());
+// Asks for the task to record in each gap, leaving the gaps given no task untracked
+fn fill_gaps(tracker: &mut Tracker, config: &Config, gaps: &[Gap]) -> Result<()> {
+    let mut lines = io::stdin().lock().lines();
+    let mut ask = |question: String| -> Option<String> {
+        print!("{}", question);
+        let _ = io::stdout().flush();
+        lines.next()?.ok().map(|line| line.trim().to_string())
+    };
+    let time = |timestamp: i64| config.timezone.format(timestamp, None, &config.date_format);
 
+    for gap in gaps {
+        let question = format!(
+            "Untracked from {} to {} ({}). Task, or nothing to skip: ",
+            time(gap.start),
+            time(gap.end),
+            gap.duration().format(config.duration_style)
+        );
+        // The end of the input skips the remaining gaps
+        let Some(name) = ask(question) else {
+            println!();
+            break;
+        };
+        if name.is_empty() {
+            continue;
+        }
+        let category = ask(format!("Category [{}]: ", config.default_category))
+            .filter(|category| !category.is_empty());
+        let task = tracker.fill(*gap, &name, category.as_deref(), &[])?;
+        output::print_filled(config, &task);
+    }
     Ok(())
 }
+
+fn get_filter(config: &Config, sub_args: &ArgMatches) -> Filter {
+    let filter = Filter::from(sub_args.get_one::<String>("filter"));
+    if sub_args.contains_id("filter") {
+        output::print_filter(config, filter);
+    }
+    filter
+}
+
+fn check_sync_path(path: &str, db: &str) -> Result<()> {
+    // Opening a missing store would create an empty one, which is never what was meant
+    let Ok(other) = Path::new(path).canonicalize() else {
+        return Err(MetronomeError::NotFound(format!(
+            "there is no store at {}",
+            path
+        )));
+    };
+    if Path::new(db).canonicalize().is_ok_and(|db| db == other) {
+        return Err(MetronomeError::Validation(String::from(
+            "cannot sync a store with itself",
+        )));
+    }
+    Ok(())
+}
+
+fn get_tags(sub_args: &ArgMatches) -> Vec<String> {
+    sub_args
+        .get_many("tag")
+        .unwrap_or_default()
+        .cloned()
+        .collect()
+}
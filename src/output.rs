@@ -0,0 +1,956 @@
+use metronome::billing::{Amount, Billed};
+use metronome::config::OutputFormat;
+use metronome::doctor::{self, Problem};
+use metronome::filters::Filter;
+use metronome::gaps::Gap;
+use metronome::invoice::{Invoice, InvoiceFormat};
+use metronome::store::{Change, ForcedChange};
+use metronome::sync::SyncReport;
+use metronome::tasktime::TaskTime;
+use metronome::{Config, Goal, Goals, Started, Status, Task, Totals};
+use serde_json::{Map, Value};
+use strum_macros::{Display, EnumString};
+
+// Presentation of the results returned by the Tracker
+
+pub fn print_started(config: &Config, started: &Started) {
+    for task in &started.stopped {
+        println!(
+            "Stopped task \"{}\" after {}",
+            task.name,
+            duration(config, task.total_time.unwrap_or_default())
+        );
+    }
+    println!(
+        "Task \"{}\" started at {}!",
+        started.task.name,
+        config
+            .timezone
+            .format(started.task.start_time, None, &config.date_format)
+    );
+}
+
+pub fn print_switched(config: &Config, switched: &Started) {
+    println!(
+        "Ended {} active tasks and started task \"{}\" at {}!",
+        switched.stopped.len(),
+        switched.task.name,
+        config
+            .timezone
+            .format(switched.task.start_time, None, &config.date_format)
+    );
+}
+
+pub fn print_ended(config: &Config, task: &Task) {
+    let end_time = task.end_time.unwrap_or_default();
+    println!(
+        "Ending task \"{}\" at {}",
+        task.name,
+        config.timezone.format(end_time, None, &config.date_format)
+    );
+    println!(
+        "Task \"{}\" ended after {}",
+        task.name,
+        duration(config, task.total_time.unwrap_or_default())
+    );
+}
+
+pub fn print_ended_all(config: &Config, tasks: &[Task]) {
+    match tasks.first().and_then(|task| task.end_time) {
+        Some(end_time) => println!(
+            "Ended {} active tasks at {}.",
+            tasks.len(),
+            config.timezone.format(end_time, None, &config.date_format)
+        ),
+        None => println!("No active tasks to end."),
+    }
+}
+
+/// A column of the task list.
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Column {
+    Id,
+    Name,
+    Start,
+    End,
+    Duration,
+    Elapsed, // Like duration, but counts active tasks up to now
+    Category,
+    Tags,
+    Status,
+    Invoice, // Number of the invoice the task was billed on
+}
+
+impl Column {
+    // Status does not seem necessary since active tasks will have NULL end times and total times
+    pub const DEFAULT: [Column; 6] = [
+        Column::Id,
+        Column::Name,
+        Column::Start,
+        Column::End,
+        Column::Duration,
+        Column::Category,
+    ];
+
+    fn header(&self) -> &'static str {
+        match self {
+            Column::Id => "ID",
+            Column::Name => "TASK",
+            Column::Start => "START TIME",
+            Column::End => "END TIME",
+            Column::Duration => "TOTAL TIME",
+            Column::Elapsed => "ELAPSED",
+            Column::Category => "CATEGORY",
+            Column::Tags => "TAGS",
+            Column::Status => "STATUS",
+            Column::Invoice => "INVOICE",
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            Column::Id => 4,
+            Column::Name => 40,
+            Column::Start | Column::End => 30,
+            Column::Duration | Column::Elapsed => 15,
+            Column::Category | Column::Tags => 20,
+            Column::Status | Column::Invoice => 8,
+        }
+    }
+
+    fn value(&self, config: &Config, task: &Task, now: i64) -> String {
+        match self {
+            Column::Id => task.id.to_string(),
+            Column::Name => task.name.clone(),
+            Column::Start => {
+                config
+                    .timezone
+                    .format(task.start_time, task.utc_offset, &config.date_format)
+            }
+            Column::End => match task.end_time {
+                Some(end_time) => {
+                    config
+                        .timezone
+                        .format(end_time, task.utc_offset, &config.date_format)
+                }
+                None => "NULL".to_string(),
+            },
+            Column::Duration => match task.total_time {
+                Some(time_s) => duration(config, time_s),
+                None => "NULL".to_string(),
+            },
+            Column::Elapsed => duration(config, task.elapsed(now)),
+            Column::Category => task.category.clone(),
+            Column::Tags => task.tags.join(","),
+            Column::Status => task.status.to_string(),
+            Column::Invoice => task
+                .invoice
+                .map(|number| number.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    // For CSV and JSON, with durations in seconds and missing values left empty
+    fn data(&self, config: &Config, task: &Task, now: i64) -> Value {
+        match self {
+            Column::Id => Value::from(task.id),
+            Column::End => match task.end_time {
+                Some(_) => Value::from(self.value(config, task, now)),
+                None => Value::Null,
+            },
+            Column::Duration => Value::from(task.total_time),
+            Column::Elapsed => Value::from(task.elapsed(now)),
+            Column::Invoice => Value::from(task.invoice),
+            _ => Value::from(self.value(config, task, now)),
+        }
+    }
+}
+
+/// The filter in effect, shown above a table only, as it would break parsing the data formats.
+pub fn print_filter(config: &Config, filter: Filter) {
+    if config.output_format == OutputFormat::Table {
+        println!("** {} **", filter);
+    }
+}
+
+pub fn print_list_rows(config: &Config, tasks: &[Task], columns: &[Column], now: i64) {
+    if config.output_format != OutputFormat::Table {
+        let rows = tasks.iter().map(|task| {
+            columns
+                .iter()
+                .map(|column| (column.to_string(), column.data(config, task, now)))
+                .collect()
+        });
+        print_data(config, &columns_names(columns), rows.collect());
+        return;
+    }
+
+    let row = |values: Vec<String>| {
+        let cells: Vec<String> = columns
+            .iter()
+            .zip(values)
+            .map(|(column, value)| format!("{:^1$}", value, column.width()))
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let headers = row(columns
+        .iter()
+        .map(|column| column.header().to_string())
+        .collect());
+    println!("{}", headers);
+    println!("{}", "=".repeat(headers.chars().count()));
+
+    for task in tasks {
+        println!(
+            "{}",
+            row(columns
+                .iter()
+                .map(|column| column.value(config, task, now))
+                .collect())
+        );
+    }
+}
+
+pub fn print_status(config: &Config, status: &Status) {
+    // A row for each active task, then one for each total
+    if config.output_format != OutputFormat::Table {
+        let names = ["entry", "id", "task", "category", "seconds"];
+        let active = status.active.iter().map(|task| {
+            [
+                Value::from("active"),
+                Value::from(task.id),
+                Value::from(task.name.clone()),
+                Value::from(task.category.clone()),
+                Value::from(task.elapsed(status.now)),
+            ]
+        });
+        let totals = [("today", status.today), ("week", status.week)]
+            .into_iter()
+            .map(|(entry, time)| {
+                [
+                    Value::from(entry),
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::from(time.total_seconds()),
+                ]
+            });
+        let rows = active.chain(totals).map(|values| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .zip(values)
+                .collect()
+        });
+        print_data(config, &names, rows.collect());
+        return;
+    }
+
+    if status.active.is_empty() {
+        println!("No active tasks.");
+    } else {
+        let headers = ("ID", "TASK", "ELAPSED", "CATEGORY");
+        println!(
+            "| {:^4} | {:^40} | {:^15} | {:^20} |",
+            headers.0, headers.1, headers.2, headers.3
+        );
+        println!("{}", "=".repeat(92));
+    }
+    for task in &status.active {
+        let elapsed = duration(config, task.elapsed(status.now));
+        println!(
+            "| {:^4} | {:^40} | {:^15} | {:^20} |",
+            task.id, task.name, elapsed, task.category
+        );
+    }
+    println!("Time tracked today: {}", duration(config, status.today));
+    println!("Time tracked this week: {}", duration(config, status.week));
+}
+
+pub fn print_goals(config: &Config, goals: &Goals) {
+    let all: Vec<&Goal> = goals
+        .today
+        .iter()
+        .chain(&goals.week)
+        .chain(&goals.budgets)
+        .collect();
+
+    if config.output_format != OutputFormat::Table {
+        let names = ["goal", "tracked", "target", "expected", "percentage"];
+        let rows = all.iter().map(|goal| {
+            let values = [
+                Value::from(goal.name.clone()),
+                Value::from(goal.tracked.total_seconds()),
+                Value::from(goal.target.total_seconds()),
+                Value::from(goal.expected.total_seconds()),
+                Value::from(round2(goal.percentage())),
+            ];
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .zip(values)
+                .collect()
+        });
+        print_data(config, &names, rows.collect());
+        return;
+    }
+
+    if all.is_empty() {
+        println!(
+            "No goals are set. Set daily_target, target.<weekday> or \
+            category.<name>.weekly_budget with `metronome config set`."
+        );
+        return;
+    }
+    const BAR_WIDTH: usize = 30;
+    for goal in all {
+        let filled = (goal.percentage().clamp(0f32, 100f32) / 100f32 * BAR_WIDTH as f32) as usize;
+        let bar = format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled));
+        // Measured against the share of the target due by now, so a week can be on track
+        // long before its target is met
+        let pace = if goal.tracked > goal.target {
+            format!("{} over", duration(config, goal.tracked - goal.target))
+        } else if goal.tracked >= goal.expected {
+            format!("{} ahead", duration(config, goal.tracked - goal.expected))
+        } else if goal.expected == goal.target {
+            format!("{} to go", duration(config, goal.expected - goal.tracked))
+        } else {
+            format!("{} behind", duration(config, goal.expected - goal.tracked))
+        };
+        println!(
+            "{:<20} [{}] {:>4.0}%  {} of {}, {}",
+            goal.name,
+            bar,
+            goal.percentage(),
+            duration(config, goal.tracked),
+            duration(config, goal.target),
+            pace
+        );
+    }
+}
+
+pub fn print_total_time_rows(config: &Config, totals: &Totals) {
+    if config.output_format != OutputFormat::Table {
+        let rows = totals.categories.iter().map(|category| {
+            let mut row = Map::from_iter([
+                (
+                    String::from("category"),
+                    Value::from(category.category.clone()),
+                ),
+                (
+                    String::from("seconds"),
+                    Value::from(category.time.total_seconds()),
+                ),
+                (
+                    String::from("rounded_seconds"),
+                    Value::from(category.rounded.total_seconds()),
+                ),
+                (
+                    String::from("percentage"),
+                    Value::from(round2(totals.percentage(category))),
+                ),
+            ]);
+            if let Some(billed) = &category.billed {
+                row.insert(
+                    String::from("billable_seconds"),
+                    Value::from(billed.time.total_seconds()),
+                );
+                row.insert(String::from("amount"), Value::from(amounts(billed)));
+            }
+            row
+        });
+        let mut names = vec!["category", "seconds", "rounded_seconds", "percentage"];
+        if totals.billed.is_some() {
+            names.extend(["billable_seconds", "amount"]);
+        }
+        print_data(config, &names, rows.collect());
+        return;
+    }
+
+    // The rounded time is only worth a column when some category is rounded
+    let rounded = config.has_rounding();
+    let row = |category: &str,
+               time: TaskTime,
+               rounded_time: TaskTime,
+               percentage: String,
+               billed: Option<&Billed>| {
+        let rounded_cell = match rounded {
+            true => format!(" {:^15} |", duration(config, rounded_time)),
+            false => String::new(),
+        };
+        let billed_cells = match billed {
+            Some(billed) => format!(
+                " {:^15} | {:^24} |",
+                duration(config, billed.time),
+                amounts(billed)
+            ),
+            None => String::new(),
+        };
+        format!(
+            "| {:^20} | {:^15} |{} {:^12} |{}",
+            category,
+            duration(config, time),
+            rounded_cell,
+            percentage,
+            billed_cells
+        )
+    };
+
+    let rounded_header = match rounded {
+        true => format!(" {:^15} |", "ROUNDED"),
+        false => String::new(),
+    };
+    let billed_headers = match totals.billed {
+        Some(_) => format!(" {:^15} | {:^24} |", "BILLABLE", "AMOUNT"),
+        None => String::new(),
+    };
+    let headers = format!(
+        "| {:^20} | {:^15} |{} {:^12} |{}",
+        "CATEGORY", "TOTAL TIME", rounded_header, "PERCENTAGE", billed_headers
+    );
+    let width = headers.chars().count();
+    println!("{}", headers);
+    println!("{}", "=".repeat(width));
+
+    for category in &totals.categories {
+        println!(
+            "{}",
+            row(
+                &category.category,
+                category.time,
+                category.rounded,
+                format!("{:.2}", totals.percentage(category)),
+                category.billed.as_ref()
+            )
+        );
+    }
+    println!("{}", "=".repeat(width));
+    println!(
+        "{}",
+        row(
+            "TOTAL",
+            totals.time,
+            totals.rounded,
+            format!("{:.2}", 100f32),
+            totals.billed.as_ref()
+        )
+    );
+}
+
+// One amount per currency, e.g. "120.00 EUR + 45.50 USD", or nothing without a rate
+fn amounts(billed: &Billed) -> String {
+    billed
+        .amounts
+        .iter()
+        .map(Amount::to_string)
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// Prints the invoice itself, and which tasks it billed on stderr so the invoice can be
+/// redirected to a file.
+pub fn print_invoice(invoice: &Invoice, format: InvoiceFormat) {
+    print!("{}", invoice.render(format));
+    let tasks = invoice
+        .lines()
+        .filter(|line| line.task_id.is_some())
+        .count();
+    eprintln!(
+        "Marked {} tasks in {} as billed on invoice {}.",
+        tasks, invoice.client, invoice.number
+    );
+}
+
+pub fn print_lock(config: &Config, locked_until: Option<i64>, changes: &[ForcedChange]) {
+    // A row for the lock if there is one, then one for each forced change
+    if config.output_format != OutputFormat::Table {
+        let names = ["entry", "time", "command", "task_id"];
+        let lock = locked_until.map(|until| {
+            [
+                Value::from("locked_until"),
+                Value::from(config.timezone.format(until, None, "%Y-%m-%d")),
+                Value::Null,
+                Value::Null,
+            ]
+        });
+        let forced = changes.iter().map(|change| {
+            [
+                Value::from("forced_change"),
+                Value::from(
+                    config
+                        .timezone
+                        .format(change.changed_at, None, &config.date_format),
+                ),
+                Value::from(change.command.clone()),
+                Value::from(change.task_id),
+            ]
+        });
+        let rows = lock.into_iter().chain(forced).map(|values| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .zip(values)
+                .collect()
+        });
+        print_data(config, &names, rows.collect());
+        return;
+    }
+
+    match locked_until {
+        Some(until) => println!(
+            "Tasks started before {} are locked.",
+            config.timezone.format(until, None, "%Y-%m-%d")
+        ),
+        None => println!("No tasks are locked."),
+    }
+    if changes.is_empty() {
+        return;
+    }
+    println!("Forced changes:");
+    for change in changes {
+        let changed_at = config
+            .timezone
+            .format(change.changed_at, None, &config.date_format);
+        match change.task_id {
+            Some(id) => println!("  {} changed task {} at {}", change.command, id, changed_at),
+            None => println!("  {} moved the lock back at {}", change.command, changed_at),
+        }
+    }
+}
+
+pub fn print_gaps(config: &Config, gaps: &[Gap]) {
+    let time = |timestamp: i64| config.timezone.format(timestamp, None, &config.date_format);
+    if config.output_format != OutputFormat::Table {
+        let rows = gaps.iter().map(|gap| {
+            let mut row = Map::new();
+            row.insert(String::from("start"), Value::from(time(gap.start)));
+            row.insert(String::from("end"), Value::from(time(gap.end)));
+            row.insert(
+                String::from("duration"),
+                Value::from(gap.duration().total_seconds()),
+            );
+            row
+        });
+        print_data(config, &["start", "end", "duration"], rows.collect());
+        return;
+    }
+
+    let headers = ("START TIME", "END TIME", "UNTRACKED");
+    println!(
+        "| {:^30} | {:^30} | {:^15} |",
+        headers.0, headers.1, headers.2
+    );
+    println!("{}", "=".repeat(85));
+    for gap in gaps {
+        println!(
+            "| {:^30} | {:^30} | {:^15} |",
+            time(gap.start),
+            time(gap.end),
+            duration(config, gap.duration())
+        );
+    }
+    if gaps.is_empty() {
+        println!("No gaps in the work hours.");
+    }
+    let untracked: TaskTime = gaps.iter().map(Gap::duration).sum();
+    println!("Time untracked: {}", duration(config, untracked));
+}
+
+pub fn print_filled(config: &Config, task: &Task) {
+    println!(
+        "Recorded task \"{}\" from {} to {}.",
+        task.name,
+        config
+            .timezone
+            .format(task.start_time, None, &config.date_format),
+        config
+            .timezone
+            .format(task.end_time.unwrap_or_default(), None, &config.date_format)
+    );
+}
+
+pub fn print_overlaps(config: &Config, overlaps: &[(Task, Task)], now: i64) {
+    let time = |timestamp: i64| config.timezone.format(timestamp, None, &config.date_format);
+    // The overlap runs from the start of the second task to whichever ends first
+    let end = |(first, second): &(Task, Task)| {
+        doctor::end_time(first, now).min(doctor::end_time(second, now))
+    };
+    if config.output_format != OutputFormat::Table {
+        let names = [
+            "first_id",
+            "first",
+            "second_id",
+            "second",
+            "start",
+            "end",
+            "duration",
+        ];
+        let rows = overlaps.iter().map(|pair| {
+            let (first, second) = pair;
+            let values = [
+                Value::from(first.id),
+                Value::from(first.name.clone()),
+                Value::from(second.id),
+                Value::from(second.name.clone()),
+                Value::from(time(second.start_time)),
+                Value::from(time(end(pair))),
+                Value::from(end(pair) - second.start_time),
+            ];
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .zip(values)
+                .collect()
+        });
+        print_data(config, &names, rows.collect());
+        return;
+    }
+
+    let headers = ("ID", "TASK", "ID", "TASK", "FROM", "TO", "OVERLAP");
+    println!(
+        "| {:^4} | {:^25} | {:^4} | {:^25} | {:^30} | {:^30} | {:^15} |",
+        headers.0, headers.1, headers.2, headers.3, headers.4, headers.5, headers.6
+    );
+    println!("{}", "=".repeat(159));
+    for pair in overlaps {
+        let (first, second) = pair;
+        println!(
+            "| {:^4} | {:^25} | {:^4} | {:^25} | {:^30} | {:^30} | {:^15} |",
+            first.id,
+            first.name,
+            second.id,
+            second.name,
+            time(second.start_time),
+            time(end(pair)),
+            duration(config, end(pair) - second.start_time)
+        );
+    }
+    if overlaps.is_empty() {
+        println!("No overlapping tasks.");
+    }
+}
+
+pub fn print_problems(config: &Config, fixed: &[Problem], problems: &[Problem], now: i64) {
+    let fixable = |problem: &Problem| problem.fixable(now, &config.timezone);
+
+    if config.output_format != OutputFormat::Table {
+        let names = ["problem", "tasks", "description", "state"];
+        let states = fixed
+            .iter()
+            .map(|problem| (problem, "fixed"))
+            .chain(problems.iter().map(|problem| {
+                (
+                    problem,
+                    if fixable(problem) {
+                        "fixable"
+                    } else {
+                        "manual"
+                    },
+                )
+            }));
+        let rows = states.map(|(problem, state)| {
+            let ids: Vec<String> = problem.task_ids().iter().map(i64::to_string).collect();
+            let values = [
+                problem.to_string(),
+                ids.join(","),
+                describe_problem(config, problem, now),
+                String::from(state),
+            ];
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .zip(values.map(Value::from))
+                .collect()
+        });
+        print_data(config, &names, rows.collect());
+        return;
+    }
+
+    if !fixed.is_empty() {
+        println!("Fixed {} problems:", fixed.len());
+        for problem in fixed {
+            println!("  {}", describe_problem(config, problem, now));
+        }
+    }
+    if problems.is_empty() {
+        println!("No problems found.");
+        return;
+    }
+    println!("Found {} problems:", problems.len());
+    for problem in problems {
+        let fix = match problem {
+            _ if !fixable(problem) => "fix by hand",
+            Problem::UnknownStatus(_) => "--fix sets it from the end time",
+            Problem::MissingTotal(_) | Problem::NegativeTotal(_) | Problem::WrongTotal(_) => {
+                "--fix sets it to the time between start and end"
+            }
+            Problem::Stale(_) => "--fix ends it at the end of the day it started",
+            Problem::Overlap(..) => "--fix ends the first when the second started",
+            Problem::DuplicateActive(_) => "--fix ends all but the latest when it started",
+        };
+        println!("  {} ({})", describe_problem(config, problem, now), fix);
+    }
+}
+
+fn describe_problem(config: &Config, problem: &Problem, now: i64) -> String {
+    let task = |task: &Task| format!("task {} \"{}\"", task.id, task.name);
+    let time = |timestamp: i64| config.timezone.format(timestamp, None, &config.date_format);
+    match problem {
+        Problem::UnknownStatus(unknown) => format!(
+            "task {} \"{}\" has the unknown status \"{}\"",
+            unknown.id, unknown.name, unknown.status
+        ),
+        Problem::MissingTotal(t) => format!("{} is complete but has no total time", task(t)),
+        Problem::NegativeTotal(t) => format!(
+            "{} has a negative total time of {}",
+            task(t),
+            duration(config, t.total_time.unwrap_or_default())
+        ),
+        Problem::WrongTotal(t) => format!(
+            "{} has a total time of {}, but ran for {}",
+            task(t),
+            duration(config, t.total_time.unwrap_or_default()),
+            duration(config, doctor::end_time(t, now) - t.start_time)
+        ),
+        Problem::Stale(t) => format!("{} has been active since {}", task(t), time(t.start_time)),
+        Problem::Overlap(first, second) => format!(
+            "{} and {} overlap from {} to {}",
+            task(first),
+            task(second),
+            time(second.start_time),
+            time(doctor::end_time(first, now).min(doctor::end_time(second, now)))
+        ),
+        Problem::DuplicateActive(tasks) => {
+            let ids: Vec<String> = tasks.iter().map(|task| task.id.to_string()).collect();
+            format!(
+                "tasks {} are all active as \"{}\"",
+                ids.join(", "),
+                tasks
+                    .first()
+                    .map(|task| task.name.as_str())
+                    .unwrap_or_default()
+            )
+        }
+    }
+}
+
+pub fn print_history(config: &Config, id: i64, history: &[Change]) {
+    // Fields that can change over the life of a task
+    const FIELDS: [Column; 8] = [
+        Column::Name,
+        Column::Category,
+        Column::Tags,
+        Column::Start,
+        Column::End,
+        Column::Duration,
+        Column::Status,
+        Column::Invoice,
+    ];
+    let changed_at = |change: &Change| {
+        config
+            .timezone
+            .format(change.changed_at, None, &config.date_format)
+    };
+
+    // One row per changed field. Every field is set when a task is created.
+    if config.output_format != OutputFormat::Table {
+        let names = ["changed_at", "command", "user", "field", "old", "new"];
+        let mut rows = vec![];
+        for change in history {
+            let now = change.changed_at;
+            for field in FIELDS {
+                // An unknown status is kept apart from the task it could not be read as
+                let old = match (&change.old_status, field) {
+                    (Some(status), Column::Status) => Some(Value::from(status.clone())),
+                    _ => change.old.as_ref().map(|old| field.data(config, old, now)),
+                };
+                let new = field.data(config, &change.new, now);
+                if old.as_ref() == Some(&new) {
+                    continue;
+                }
+                let values = [
+                    Value::from(changed_at(change)),
+                    Value::from(change.command.clone()),
+                    Value::from(change.user.clone()),
+                    Value::from(field.to_string()),
+                    old.unwrap_or(Value::Null),
+                    new,
+                ];
+                rows.push(
+                    names
+                        .iter()
+                        .map(|name| name.to_string())
+                        .zip(values)
+                        .collect(),
+                );
+            }
+        }
+        print_data(config, &names, rows);
+        return;
+    }
+
+    if history.is_empty() {
+        println!("No changes to task {} have been recorded.", id);
+        return;
+    }
+    for change in history {
+        println!(
+            "{}  {} by {}",
+            changed_at(change),
+            change.command,
+            change.user
+        );
+        let now = change.changed_at;
+        for field in FIELDS {
+            let new = field.value(config, &change.new, now);
+            match &change.old {
+                Some(old) => {
+                    let old = match (&change.old_status, field) {
+                        (Some(status), Column::Status) => status.clone(),
+                        _ => field.value(config, old, now),
+                    };
+                    if old != new {
+                        println!("  {}: {} -> {}", field, old, new);
+                    }
+                }
+                // Fields a new task leaves unset are left out
+                None if new.is_empty() || new == "NULL" => {}
+                None => println!("  {}: {}", field, new),
+            }
+        }
+    }
+}
+
+pub fn print_synced(config: &Config, other: &str, report: &SyncReport) {
+    // A row for each task pulled, pushed or in conflict, with its ID in this store
+    if config.output_format != OutputFormat::Table {
+        let names = ["change", "id", "uuid", "task", "kept"];
+        let row = |change: &str, id: Option<i64>, task: &Task, kept: Value| {
+            [
+                Value::from(change),
+                Value::from(id),
+                Value::from(task.uuid.clone()),
+                Value::from(task.name.clone()),
+                kept,
+            ]
+        };
+        let pulled = report
+            .pulled
+            .iter()
+            .map(|task| row("pulled", Some(task.id), task, Value::Null));
+        // Pushed tasks come back with their ID in the other store, so only the UUID matches
+        let pushed = report
+            .pushed
+            .iter()
+            .map(|task| row("pushed", None, task, Value::Null));
+        let conflicts = report.conflicts.iter().map(|conflict| {
+            let kept = if conflict.kept_remote() {
+                "other"
+            } else {
+                "local"
+            };
+            row(
+                "conflict",
+                Some(conflict.local.id),
+                conflict.kept(),
+                Value::from(kept),
+            )
+        });
+        let rows = pulled.chain(pushed).chain(conflicts).map(|values| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .zip(values)
+                .collect()
+        });
+        print_data(config, &names, rows.collect());
+        return;
+    }
+
+    println!(
+        "Pulled {} tasks from {} and pushed {} tasks to it.",
+        report.pulled.len(),
+        other,
+        report.pushed.len()
+    );
+    if report.conflicts.is_empty() {
+        return;
+    }
+
+    println!(
+        "Resolved {} conflicts by keeping the most recently modified task:",
+        report.conflicts.len()
+    );
+    let headers = ("ID", "TASK", "KEPT", "LOCAL MODIFIED", "OTHER MODIFIED");
+    println!(
+        "| {:^4} | {:^40} | {:^6} | {:^30} | {:^30} |",
+        headers.0, headers.1, headers.2, headers.3, headers.4
+    );
+    println!("{}", "=".repeat(128));
+
+    for conflict in &report.conflicts {
+        let kept = if conflict.kept_remote() {
+            "other"
+        } else {
+            "local"
+        };
+        println!(
+            "| {:^4} | {:^40} | {:^6} | {:^30} | {:^30} |",
+            conflict.local.id,
+            conflict.kept().name,
+            kept,
+            config
+                .timezone
+                .format(conflict.local.modified_at, None, &config.date_format),
+            config
+                .timezone
+                .format(conflict.remote.modified_at, None, &config.date_format)
+        );
+    }
+}
+
+fn duration(config: &Config, time: impl Into<TaskTime>) -> String {
+    time.into().format(config.duration_style)
+}
+
+// Percentages are f32, which print with spurious digits once widened for JSON
+fn round2(value: f32) -> f64 {
+    (value as f64 * 100.0).round() / 100.0
+}
+
+fn columns_names(columns: &[Column]) -> Vec<String> {
+    columns.iter().map(Column::to_string).collect()
+}
+
+// Prints rows of named values as CSV with a header line, or as a JSON array of objects
+fn print_data<S: AsRef<str>>(config: &Config, names: &[S], rows: Vec<Map<String, Value>>) {
+    if config.output_format == OutputFormat::Json {
+        println!("{}", Value::from(rows));
+        return;
+    }
+
+    let names: Vec<&str> = names.iter().map(AsRef::as_ref).collect();
+    println!("{}", names.join(","));
+    for row in rows {
+        let cells: Vec<String> = names
+            .iter()
+            .map(|name| match row.get(*name) {
+                Some(Value::String(value)) => csv_field(value),
+                Some(Value::Null) | None => String::new(),
+                Some(value) => value.to_string(),
+            })
+            .collect();
+        println!("{}", cells.join(","));
+    }
+}
+
+// Quotes a CSV field when it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
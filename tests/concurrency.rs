@@ -0,0 +1,119 @@
+//! Several processes writing to the same store at once must not lose or double-end tasks.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+use metronome::store::{self, TaskQuery};
+use metronome::TaskStatus;
+
+const WRITERS: usize = 6; // Processes starting tasks, and ending every other one
+const TASKS_PER_WRITER: usize = 15;
+const ENDERS: usize = 3; // Processes running end --all alongside the writers
+const END_ALL_RUNS: usize = 20;
+
+fn metronome(db: &Path, args: &[&str]) -> (i32, String) {
+    // A config file of the user running the tests could change the output parsed here
+    let output = Command::new(env!("CARGO_BIN_EXE_metronome"))
+        .env("METRONOME_CONFIG", db.with_extension("toml"))
+        .env_remove("METRONOME_SINGLE_ACTIVE")
+        .arg("--db")
+        .arg(db)
+        .args(args)
+        .output()
+        .expect("metronome could not be run");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    )
+}
+
+// Number of tasks an end --all run reports ending
+fn ended_all(db: &Path) -> usize {
+    let (code, stdout) = metronome(db, &["end", "--all"]);
+    assert_eq!(0, code, "end --all failed: {}", stdout);
+    stdout
+        .strip_prefix("Ended ")
+        .and_then(|rest| rest.split(' ').next())
+        .map_or(0, |count| count.parse().unwrap())
+}
+
+fn stress(db: PathBuf) {
+    let writers = (0..WRITERS).map(|writer| {
+        let db = db.clone();
+        thread::spawn(move || {
+            let mut ended = 0;
+            for task in 0..TASKS_PER_WRITER {
+                let name = format!("writer{}-task{}", writer, task);
+                let (code, stdout) = metronome(&db, &["start", &name]);
+                assert_eq!(0, code, "start failed: {}", stdout);
+                if task % 2 == 1 {
+                    // An end --all may have got there first, which is reported as not found
+                    match metronome(&db, &["end", &name]) {
+                        (0, _) => ended += 1,
+                        (4, _) => {}
+                        (code, stdout) => panic!("end exited with {}: {}", code, stdout),
+                    }
+                }
+            }
+            ended
+        })
+    });
+    let enders = (0..ENDERS).map(|_| {
+        let db = db.clone();
+        thread::spawn(move || (0..END_ALL_RUNS).map(|_| ended_all(&db)).sum::<usize>())
+    });
+
+    let handles: Vec<_> = writers.chain(enders).collect();
+    let mut ended: usize = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .sum();
+    ended += ended_all(&db);
+
+    let store = store::open_read_only(&db).unwrap();
+    let tasks = store.query(&TaskQuery::default()).unwrap();
+
+    // Every task was stored, with its own ID and UUID
+    assert_eq!(WRITERS * TASKS_PER_WRITER, tasks.len());
+    let mut ids: Vec<i64> = tasks.iter().map(|task| task.id).collect();
+    ids.dedup();
+    assert_eq!(tasks.len(), ids.len());
+    let mut uuids: Vec<&str> = tasks.iter().map(|task| task.uuid.as_str()).collect();
+    uuids.sort();
+    uuids.dedup();
+    assert_eq!(tasks.len(), uuids.len());
+
+    // Every task was ended exactly once
+    assert!(tasks.iter().all(|task| task.status == TaskStatus::Complete));
+    assert_eq!(tasks.len(), ended);
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("metronome_stress_{}_{}", std::process::id(), name));
+    remove(&path);
+    path
+}
+
+fn remove(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        let _ = std::fs::remove_file(file);
+    }
+}
+
+#[test]
+fn test_concurrent_sqlite() {
+    let db = temp_path("tasks.db");
+    stress(db.clone());
+    remove(&db);
+}
+
+#[test]
+fn test_concurrent_jsonl() {
+    let db = temp_path("tasks.jsonl");
+    stress(db.clone());
+    remove(&db);
+}